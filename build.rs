@@ -0,0 +1,30 @@
+use std::process::Command;
+
+fn output(cmd: &str, args: &[&str]) -> String {
+    Command::new(cmd)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map_or_else(|| "unknown".to_string(), |s| s.trim().to_string())
+}
+
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let build_time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    println!(
+        "cargo:rustc-env=TAGME_GIT_SHA={}",
+        output("git", &["rev-parse", "--short", "HEAD"])
+    );
+    println!("cargo:rustc-env=TAGME_BUILD_TIME={build_time}");
+    println!(
+        "cargo:rustc-env=TAGME_RUSTC={}",
+        output(&rustc, &["--version"])
+    );
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
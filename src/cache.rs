@@ -0,0 +1,165 @@
+use axum::body::{Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::header::{self, HeaderMap, HeaderValue};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::models::AppState;
+
+struct Entry {
+    stored: Instant,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+// 公开 GET 的短期缓存; ttl 为 0 表示不缓存
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl ResponseCache {
+    /// At most `max_entries` responses are kept; the oldest is evicted first.
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn insert(&self, key: String, entry: Entry) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&key) && entries.len() >= self.max_entries {
+            // 先丢过期的, 仍然满了再丢最旧的
+            entries.retain(|_, e| e.stored.elapsed() < self.ttl);
+            if entries.len() >= self.max_entries {
+                let oldest: Option<String> = entries
+                    .iter()
+                    .min_by_key(|(_, e)| e.stored)
+                    .map(|(k, _)| k.clone());
+                oldest.map(|k| entries.remove(&k));
+            }
+        }
+        entries.insert(key, entry);
+    }
+}
+
+// 除了不影响响应内容的参数 (pretty 在缓存外层处理, _ 是随机的 cache buster), 其余参数都进入缓存键
+const IGNORED_PARAMS: &[&str] = &["_", "pretty"];
+
+fn cache_key(request: &Request) -> String {
+    let mut params: Vec<&str> = request
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+            !pair.is_empty() && !IGNORED_PARAMS.contains(&name)
+        })
+        .collect();
+    params.sort_unstable();
+    params.dedup();
+    format!("{}?{}", request.uri().path(), params.join("&"))
+}
+
+// If-None-Match 按弱比较, 与 handler 自己处理时一致
+fn etag_matches(request: &Request, etag: &HeaderValue) -> bool {
+    let etag = etag.to_str().unwrap_or_default().trim_start_matches("W/");
+    request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == "*" || t == etag)
+        })
+}
+
+// 带凭据的请求可能读到私有内容, 一律不缓存
+fn is_cacheable(request: &Request) -> bool {
+    let path = request.uri().path();
+    let has_embed_query = request
+        .uri()
+        .query()
+        .is_some_and(|q| q.split('&').any(|pair| pair.starts_with("embed=")));
+    request.method() == Method::GET
+        && !request.headers().contains_key(header::AUTHORIZATION)
+        && !request.headers().contains_key("X-Api-Key")
+        && !request.headers().contains_key("X-Embed-Token")
+        && !has_embed_query
+        && crate::token::Token::from_cookie(request.headers()).is_none()
+        && (matches!(path, "/top" | "/recent" | "/stats")
+            || path.starts_with("/topic/")
+            || path.starts_with("/stats/"))
+}
+
+/// Serves anonymous public reads from memory, tagging them with `X-Cache` and `Age`.
+pub async fn cache_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let cache = &state.response_cache;
+    if cache.ttl.is_zero() || !is_cacheable(&request) {
+        return next.run(request).await;
+    }
+    let key: String = cache_key(&request);
+
+    if let Some(entry) = cache.entries.lock().unwrap().get(&key) {
+        let age = entry.stored.elapsed();
+        if age < cache.ttl {
+            let revalidated: Option<&HeaderValue> = entry
+                .headers
+                .get(header::ETAG)
+                .filter(|etag| etag_matches(&request, etag));
+            let mut response = match revalidated {
+                Some(etag) => {
+                    (StatusCode::NOT_MODIFIED, [(header::ETAG, etag.clone())]).into_response()
+                }
+                None => {
+                    let mut response = (StatusCode::OK, entry.body.clone()).into_response();
+                    response.headers_mut().extend(entry.headers.clone());
+                    response
+                }
+            };
+            response
+                .headers_mut()
+                .insert(header::AGE, HeaderValue::from(age.as_secs()));
+            response
+                .headers_mut()
+                .insert("X-Cache", HeaderValue::from_static("HIT"));
+            return response;
+        }
+    }
+
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    cache.insert(
+        key,
+        Entry {
+            stored: Instant::now(),
+            headers: parts.headers.clone(),
+            body: body.clone(),
+        },
+    );
+
+    parts
+        .headers
+        .insert("X-Cache", HeaderValue::from_static("MISS"));
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,198 @@
+use axum::Json;
+use axum::extract::rejection::JsonRejection;
+use axum::http::StatusCode;
+use axum::http::header::{self, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::json;
+
+/// A failed field. `kind` is the stable machine-readable code; `message` is
+/// for humans and free to change.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub kind: &'static str,
+    pub message: &'static str,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, kind: &'static str, message: &'static str) -> Self {
+        Self {
+            field: field.into(),
+            kind,
+            message,
+        }
+    }
+
+    /// Same error reported under another field name.
+    pub fn for_field(self, field: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            ..self
+        }
+    }
+}
+
+// 没有指定 kind 的状态错误按 HTTP 状态归类
+fn status_kind(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PRECONDITION_FAILED => "precondition_failed",
+        StatusCode::UNPROCESSABLE_ENTITY => "validation_failed",
+        StatusCode::LOCKED => "locked",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        s if s.is_server_error() => "internal",
+        _ => "error",
+    }
+}
+
+// {"error":{"code":404,"kind":"not_found","message":"..."}}
+/// Clients should branch on `kind`, not on `message`. Errors built from
+/// `(status, kind, message)` carry their own kind; a bare `(status, message)`
+/// falls back to one derived from the HTTP status (`bad_request`,
+/// `unauthorized`, `forbidden`, `not_found`, `conflict`, ...).
+///
+/// A single failed field is answered as `400` with that field's kind at the
+/// top level (a too-long tag is `400 tag_too_long`); several at once are
+/// `422 validation_failed`, each listed under `fields`.
+#[derive(Debug)]
+pub enum AppError {
+    Status {
+        status: StatusCode,
+        kind: &'static str,
+        message: &'static str,
+    },
+    NotFound {
+        entity: &'static str,
+        key: String,
+    },
+    RateLimited {
+        retry_after: u64,
+    },
+    Validation(Vec<FieldError>),
+    BadJson {
+        status: StatusCode,
+        detail: String,
+    }, // serde 的出错位置和原因
+}
+
+impl AppError {
+    pub fn not_found_named(entity: &'static str, key: impl std::fmt::Display) -> Self {
+        Self::NotFound {
+            entity,
+            key: key.to_string(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Status { status, .. } => *status,
+            Self::NotFound { .. } => StatusCode::NOT_FOUND,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::Validation(fields) if fields.len() == 1 => StatusCode::BAD_REQUEST,
+            Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::BadJson { status, .. } => *status,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Status { kind, .. } => kind,
+            Self::NotFound { entity, .. } => match *entity {
+                "Topic" => "topic_not_found",
+                "User" => "user_not_found",
+                "Tag" => "tag_not_found",
+                _ => "not_found",
+            },
+            Self::RateLimited { .. } => "rate_limited",
+            Self::Validation(fields) => match fields.as_slice() {
+                [field] => field.kind,
+                _ => "validation_failed",
+            },
+            Self::BadJson { .. } => "invalid_json",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::Status { message, .. } => message.to_string(),
+            Self::NotFound { entity, key } => format!("{entity} '{key}' not found"),
+            Self::RateLimited { .. } => "Rate limit exceeded".to_string(),
+            Self::Validation(_) => "Validation failed".to_string(),
+            Self::BadJson { detail, .. } => detail.clone(),
+        }
+    }
+}
+
+impl From<(StatusCode, &'static str)> for AppError {
+    fn from((status, message): (StatusCode, &'static str)) -> Self {
+        Self::Status {
+            status,
+            kind: status_kind(status),
+            message,
+        }
+    }
+}
+
+impl From<(StatusCode, &'static str, &'static str)> for AppError {
+    fn from((status, kind, message): (StatusCode, &'static str, &'static str)) -> Self {
+        Self::Status {
+            status,
+            kind,
+            message,
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::BadJson {
+            status: rejection.status(),
+            detail: rejection.body_text(),
+        }
+    }
+}
+
+impl From<FieldError> for AppError {
+    fn from(error: FieldError) -> Self {
+        Self::Validation(vec![error])
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let mut body = json!({
+            "error": {
+                "code": status.as_u16(),
+                "kind": self.kind(),
+                "message": self.message(),
+            }
+        });
+        if let Self::Validation(fields) = &self {
+            let fields: Vec<serde_json::Value> = fields
+                .iter()
+                .map(|f| {
+                    json!({
+                        "field": f.field,
+                        "kind": f.kind,
+                        "message": f.message,
+                    })
+                })
+                .collect();
+            body["error"]["fields"] = json!(fields);
+        }
+        let mut response = (status, Json(body)).into_response();
+        if let Self::RateLimited { retry_after } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
+    }
+}
@@ -1,14 +1,468 @@
 use axum::Router;
-use tracing::debug;
+use axum::extract::ConnectInfo;
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioTimer};
+use hyper_util::server::conn::auto::Builder as AutoBuilder;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::Service;
+use tracing::{Span, debug, field::Empty};
 
+use crate::models::AppState;
+
+pub mod cache;
+pub mod error;
 pub mod models;
+pub mod pagination;
+pub mod rate;
+pub mod render;
+pub mod search;
 pub mod token;
+pub mod validate;
+pub mod webhook;
+
+/// Connection tuning; the default is plain HTTP/1.1 through `axum::serve`.
+#[derive(Default)]
+pub struct ServeOptions {
+    pub http2: bool, // 同时接受 h2c (prior knowledge)
+    pub http2_keep_alive_interval: Option<Duration>,
+    pub header_read_timeout: Option<Duration>,
+}
 
-pub async fn serve(app: Router, port: u16) {
-    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+/// How long in-flight requests may take to finish once shutdown starts;
+/// open SSE streams would otherwise hold the process forever.
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// Serves `app` until `shutdown` resolves, then stops accepting and waits
+/// up to `SHUTDOWN_GRACE` for open connections.
+pub async fn serve(
+    app: Router,
+    port: u16,
+    options: ServeOptions,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    // 收到信号后才开始计算宽限期
+    let (closing_tx, mut closing_rx) = tokio::sync::watch::channel(());
+    if !options.http2 && options.header_read_timeout.is_none() {
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+            shutdown.await;
+            let _ = closing_tx.send(());
+        });
+        tokio::select! {
+            result = server => result.unwrap(),
+            _ = async {
+                let _ = closing_rx.changed().await;
+                tokio::time::sleep(SHUTDOWN_GRACE).await;
+            } => tracing::warn!("connections still open after the shutdown grace period"),
+        }
+        return;
+    }
+
+    let builder = Arc::new(server_builder(&options));
+    let mut shutdown = std::pin::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            conn = listener.accept() => match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::error!("failed to accept connection: {e}");
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let (app, builder) = (app.clone(), builder.clone());
+        let mut closing = closing_rx.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(peer));
+                app.clone().call(request.map(axum::body::Body::new))
+            });
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let mut conn = std::pin::pin!(builder.serve_connection_with_upgrades(io, service));
+            let result = tokio::select! {
+                result = conn.as_mut() => result,
+                _ = closing.changed() => {
+                    conn.as_mut().graceful_shutdown();
+                    conn.await
+                }
+            };
+            if let Err(e) = result {
+                debug!("connection from {peer} closed: {e}");
+            }
+        });
+    }
+    // 每个连接持有一个 receiver, 全部结束后 closed 返回
+    drop(closing_rx);
+    let _ = closing_tx.send(());
+    if tokio::time::timeout(SHUTDOWN_GRACE, closing_tx.closed())
+        .await
+        .is_err()
+    {
+        tracing::warn!("connections still open after the shutdown grace period");
+    }
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on unix.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutting down");
+}
+
+pub fn server_builder(options: &ServeOptions) -> AutoBuilder<TokioExecutor> {
+    let mut builder = AutoBuilder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(options.header_read_timeout);
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .keep_alive_interval(options.http2_keep_alive_interval);
+    match options.http2 {
+        true => builder,
+        false => builder.http1_only(),
+    }
+}
+
+/// Flushes sled every `interval` until `shutdown` changes or is dropped;
+/// the final flush is left to the caller, after the server has drained.
+pub async fn flush_loop(
+    db: sled::Db,
+    interval: std::time::Duration,
+    mut shutdown: tokio::sync::watch::Receiver<()>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await; // 第一次立即返回, 跳过
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        match db.flush_async().await {
+            Ok(bytes) => debug!("flushed {bytes} bytes"),
+            Err(e) => tracing::error!("failed to flush database: {e}"),
+        }
+    }
+}
+
+/// Sweeps expired token revocations every `interval` until `shutdown`
+/// changes or is dropped; a sweep already running is finished first.
+pub async fn revocation_sweep_loop(
+    db: sled::Db,
+    interval: std::time::Duration,
+    mut shutdown: tokio::sync::watch::Receiver<()>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.changed() => break,
+        }
+        let sweep_db = db.clone();
+        match tokio::task::spawn_blocking(move || token::sweep_revocations(&sweep_db)).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(removed)) => tracing::info!("swept {removed} expired revocations"),
+            Ok(Err(e)) => tracing::error!("failed to sweep revocations: {e:?}"),
+            Err(e) => tracing::error!("failed to sweep revocations: {e}"),
+        }
+    }
+}
+
+/// Answers `503` for everything but `/health` until the database is open.
+pub async fn ready_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match state.is_ready() || request.uri().path() == "/health" {
+        true => next.run(request).await,
+        false => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "5")],
+            "Service starting",
+        )
+            .into_response(),
+    }
+}
+
+/// Re-serializes JSON responses with indentation when `?pretty=true` is set.
+pub async fn pretty_json_middleware(request: Request, next: Next) -> Response {
+    let pretty: bool = request
+        .uri()
+        .query()
+        .is_some_and(|q| q.split('&').any(|pair| pair == "pretty=true"));
+    let response = next.run(request).await;
+    let is_json: bool = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if !pretty || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    // preserve_order 保证重新序列化后字段和 tag 顺序不变
+    let body: Vec<u8> = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .and_then(|value| serde_json::to_vec_pretty(&value))
+        .unwrap_or_else(|_| bytes.to_vec());
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, axum::body::Body::from(body))
+}
+
+/// Sheds requests beyond `max_concurrent_requests` with `503`, except `/health`.
+pub async fn load_shed_middleware(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(slots) = &state.request_slots else {
+        return next.run(request).await;
+    };
+    if request.uri().path() == "/health" {
+        return next.run(request).await;
+    }
+    match slots.clone().try_acquire_owned() {
+        Ok(_permit) => next.run(request).await,
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "1")],
+            "Server busy",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+/// Takes `X-Request-Id` from the client or proxy, or generates one, and echoes it.
+/// Must wrap the trace layer so `make_span` can see it.
+pub async fn request_id_middleware(mut request: Request, next: Next) -> Response {
+    let id: String = request
+        .headers()
+        .get("X-Request-Id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|s| !s.is_empty() && s.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            let bytes: [u8; 16] = rand::random();
+            bytes.iter().map(|b| format!("{b:02x}")).collect()
+        });
+    request.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert("X-Request-Id", value);
+    }
+    response
+}
+
+pub fn make_span(request: &Request) -> Span {
+    let request_id: &str = request
+        .extensions()
+        .get::<RequestId>()
+        .map_or("", |id| id.0.as_str());
+    tracing::debug_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        action = Empty,
+        topic = Empty,
+        uid = Empty,
+    )
+}
+
+/// Attaches handler context to the current request span. Never pass secrets here.
+pub fn record_ctx(action: &str, topic: &str, uid: Option<u64>) {
+    let span = Span::current();
+    span.record("action", action);
+    span.record("topic", topic);
+    if let Some(uid) = uid {
+        span.record("uid", uid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tracing::field::{Field, Visit};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    // 收集所有 span 字段, 后写入的覆盖先写入的
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<HashMap<String, String>>>);
+
+    impl Visit for Capture {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            let mut fields = self.0.lock().unwrap();
+            fields.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let mut fields = self.0.lock().unwrap();
+            fields.insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: tracing::Subscriber> Layer<S> for Capture {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: Context<'_, S>,
+        ) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(
+            &self,
+            _id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: Context<'_, S>,
+        ) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    // 用给定配置起一个只回 "ok" 的服务, 返回用 h2c 请求它的结果
+    async fn h2c_request(options: ServeOptions) -> reqwest::Result<reqwest::Response> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let builder = server_builder(&options);
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = hyper::service::service_fn(|_: Request<Incoming>| async {
+                Ok::<_, std::convert::Infallible>(Response::new(axum::body::Body::from("ok")))
+            });
+            let io = hyper_util::rt::TokioIo::new(stream);
+            let _ = builder.serve_connection(io, service).await;
+        });
+        reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap()
+            .get(format!("http://{addr}/"))
+            .send()
+            .await
+    }
+
+    #[tokio::test]
+    async fn http2_option_enables_prior_knowledge() {
+        let response = h2c_request(ServeOptions {
+            http2: true,
+            http2_keep_alive_interval: Some(Duration::from_secs(10)),
+            header_read_timeout: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(response.version(), axum::http::Version::HTTP_2);
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        // 默认只说 HTTP/1.1
+        assert!(h2c_request(ServeOptions::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn flush_loop_flushes_within_interval() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        db.insert("key", vec![1u8; 4096]).unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
+        let task = tokio::spawn(flush_loop(
+            db.clone(),
+            Duration::from_millis(20),
+            shutdown_rx,
+        ));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // 已经被定时任务写盘, 再 flush 没有脏数据
+        assert_eq!(db.flush_async().await.unwrap(), 0);
+
+        // 收到关闭信号后退出, 不再定时写盘
+        shutdown_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn revocation_sweep_loop_stops_on_shutdown() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
+        let task = tokio::spawn(revocation_sweep_loop(
+            db,
+            Duration::from_secs(3600),
+            shutdown_rx,
+        ));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(shutdown_tx);
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn request_span_carries_handler_context() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let mut request = Request::get("/add/tag/rust")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(RequestId("abc".to_string()));
+            let span = make_span(&request);
+            let _entered = span.enter();
+            record_ctx("add_tag", "rust", Some(7));
+        });
+
+        let fields = capture.0.lock().unwrap();
+        for (name, value) in [
+            ("request_id", "abc"),
+            ("method", "GET"),
+            ("uri", "/add/tag/rust"),
+            ("action", "add_tag"),
+            ("topic", "rust"),
+            ("uid", "7"),
+        ] {
+            assert_eq!(fields.get(name).map(String::as_str), Some(value), "{name}");
+        }
+    }
 }
 
 // pub async fn remove_tail_middleware(request: Request, next: Next) -> Response {
@@ -1,419 +1,7057 @@
 use axum::body::Body;
 use axum::extract::{Extension, Path, Query, State};
-use axum::http::{Response, StatusCode};
-use axum::response::{Html, IntoResponse};
+use axum::http::{HeaderMap, HeaderValue, Response, StatusCode, header};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Redirect};
 use axum::routing::{get, post};
 use axum::{Json, middleware};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::convert::Infallible;
+use std::io::Write;
+use std::sync::{Arc, OnceLock};
 use tokio::sync::Mutex;
+use tokio_stream::{Stream, StreamExt};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{error, info};
 
-use tagme::models::{AppState, Top, Topic, UserData, UserInfo, UserStatus, with_transaction};
-use tagme::token::{OptionalToken, Token};
+use tagme::cache::ResponseCache;
+use tagme::error::{AppError, FieldError};
+use tagme::models::{
+    AppState, DbHelper, DbType, FeaturedAuthors, LoginPolicy, Motd, Revoked, TagEvent, TagHistory,
+    TagLimits, TagSynonym, Top, Topic, TopicCreated, UserData, UserInfo, UserStatus, Visibility,
+    canonical_tag, fold_tag, now, validate_description, validate_url, with_transaction,
+};
+use tagme::pagination::{PageConfig, Pagination};
+use tagme::rate::RateLimiter;
+use tagme::search;
+use tagme::token::{ApiScope, EmbedGrant, EmbedToken, OAuthState, OptionalToken, Token};
+use tagme::validate::{AppJson, Validate, ValidatedJson};
+
+const TOKEN_KEY_FILE: &str = "token.key";
 
 #[tokio::main]
 async fn main() {
-    use middleware::from_fn as mw_fn;
-
-    #[derive(Deserialize)]
+    #[derive(Deserialize, Serialize)]
     struct Config {
         cache: u64,
         compression: i32,
         github_oauth_client_id: String,
         github_oauth_client_secrets: String,
+        #[serde(default)]
+        oauth_redirect_uri: String,
+        #[serde(default)]
+        base_path: String,
+        #[serde(default)]
+        lenient_decode: bool,
+        #[serde(default = "default_rate")]
+        user_rate_per_minute: u32,
+        #[serde(default = "default_rate")]
+        ip_rate_per_minute: u32,
+        #[serde(default)]
+        trusted_proxies: Vec<std::net::IpAddr>, // 空表示忽略 X-Forwarded-For
+        #[serde(default)]
+        new_account_grace_secs: i64, // 0 表示不区分新账号
+        #[serde(default = "new_account_rate")]
+        new_account_rate_per_minute: u32,
+        #[serde(default = "anonymous_vote_weight")]
+        anonymous_vote_weight: u32, // 0 表示匿名投票不计数
+        #[serde(default)]
+        login_allowlist: Vec<String>,
+        #[serde(default)]
+        login_denylist: Vec<String>,
+        #[serde(default)]
+        read_api_keys: Vec<String>,
+        #[serde(default)]
+        write_api_keys: HashMap<String, u64>, // key -> 服务 uid
+        #[serde(default = "default_page_size")]
+        default_page_size: usize,
+        #[serde(default = "max_page_size")]
+        max_page_size: usize,
+        #[serde(default)]
+        topic_name_pattern: Option<String>, // 如 ^[a-z0-9-]+$
+        #[serde(default = "flush_interval_secs")]
+        flush_interval_secs: u64, // 0 表示不定时 flush
+        #[serde(default = "revocation_sweep_interval_secs")]
+        revocation_sweep_interval_secs: u64, // 0 表示不清理过期的吊销记录
+        #[serde(default)]
+        tag_blocklist: Vec<String>,
+        #[serde(default)]
+        require_at_least_one_tag: bool, // 只检查创建, 编辑不受影响
+        #[serde(default = "min_tag_len")]
+        min_tag_len: usize, // 按字符计, 不含首尾空白
+        #[serde(default = "max_tag_chars")]
+        max_tag_chars: usize,
+        #[serde(default = "max_tags_per_topic")]
+        max_tags_per_topic: usize, // 只限制创建 topic 时一次带的 tag
+        #[serde(default)]
+        github_webhook_secret: String,
+        #[serde(default)]
+        snapshot_dir: String, // 空表示关闭 /private/admin/snapshot
+        #[serde(default)]
+        use_cookie_auth: bool, // 默认只用 Authorization 头
+        #[serde(default)]
+        token_secret: String, // 32 字节, hex 或 base64; 空表示使用 token.key
+        #[serde(default)]
+        response_cache_ttl_secs: u64, // 0 表示不缓存
+        #[serde(default = "response_cache_max_entries")]
+        response_cache_max_entries: usize,
+        #[serde(default)]
+        max_concurrent_requests: usize, // 0 表示不限
+        #[serde(default)]
+        cors_allowed_origins: Vec<String>, // 空表示不允许跨域, "*" 表示任意, 可写 https://*.example.com
+        #[serde(default)]
+        cors_allow_credentials: bool,
+        #[serde(default)]
+        cors_max_age_secs: u64,
+        #[serde(default)]
+        http2: bool, // 默认只开 HTTP/1.1
+        #[serde(default)]
+        http2_keep_alive_interval_secs: u64, // 0 表示不发 PING
+        #[serde(default)]
+        header_read_timeout_secs: u64, // 0 表示不限
+    }
+    fn default_rate() -> u32 {
+        60
+    }
+    fn new_account_rate() -> u32 {
+        10
+    }
+    fn anonymous_vote_weight() -> u32 {
+        1
+    }
+    fn default_page_size() -> usize {
+        50
+    }
+    fn max_page_size() -> usize {
+        200
+    }
+    fn flush_interval_secs() -> u64 {
+        30
+    }
+    fn revocation_sweep_interval_secs() -> u64 {
+        3600
+    }
+    fn min_tag_len() -> usize {
+        TagLimits::default().min_chars
+    }
+    fn max_tag_chars() -> usize {
+        TagLimits::default().max_chars
+    }
+    fn max_tags_per_topic() -> usize {
+        TagLimits::default().max_per_topic
+    }
+    fn response_cache_max_entries() -> usize {
+        1024
     }
     let config: Config = toml::from_str(&std::fs::read_to_string("config.toml").unwrap()).unwrap();
+    let effective_config: serde_json::Value = redact_config(json!(config));
 
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::DEBUG)
         .without_time() // systemd it
         .init();
 
+    // 未配置时使用 data.sled 旁的 token.key, 不存在则生成
+    match config.token_secret.is_empty() {
+        true => tagme::token::load_secret_key(TOKEN_KEY_FILE).unwrap(),
+        false => tagme::token::set_secret_key(&config.token_secret).unwrap(),
+    }
+
+    // 会被拼进重定向和 HTML, 只允许简单路径
+    assert!(
+        config.base_path.is_empty()
+            || (config.base_path.starts_with('/')
+                && !config.base_path.ends_with('/')
+                && config
+                    .base_path
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "/-_".contains(c))),
+        "base_path must look like /api"
+    );
+
+    let cors = cors_layer(
+        &config.cors_allowed_origins,
+        config.cors_allow_credentials,
+        config.cors_max_age_secs,
+    );
+
     let state = Arc::new(AppState {
-        db: sled::Config::new()
-            .path("data.sled")
-            .cache_capacity(config.cache)
-            .compression_factor(config.compression)
-            .open()
-            .unwrap(),
+        db: OnceLock::new(),
         oauth_client_id: config.github_oauth_client_id,
         oauth_client_secrets: config.github_oauth_client_secrets,
+        oauth_redirect_uri: config.oauth_redirect_uri,
+        base_path: config.base_path.clone(),
+        effective_config,
+        user_limiter: RateLimiter::new(config.user_rate_per_minute),
+        young_limiter: RateLimiter::new(config.new_account_rate_per_minute),
+        new_account_grace_secs: config.new_account_grace_secs,
+        anonymous_vote_weight: config.anonymous_vote_weight,
+        require_tag: config.require_at_least_one_tag,
+        cookie_auth: config.use_cookie_auth,
+        ip_limiter: RateLimiter::new(config.ip_rate_per_minute),
+        trusted_proxies: config.trusted_proxies.into_iter().collect(),
+        login_policy: LoginPolicy {
+            allowlist: config.login_allowlist,
+            denylist: config.login_denylist,
+        },
+        api_keys: config
+            .read_api_keys
+            .into_iter()
+            .map(|key| (key, ApiScope::Read))
+            .chain(
+                config
+                    .write_api_keys
+                    .into_iter()
+                    .map(|(key, uid)| (key, ApiScope::Write(uid))),
+            )
+            .collect(),
+        page_config: PageConfig {
+            default_size: config.default_page_size,
+            max_size: config.max_page_size,
+        },
+        lenient_decode: config.lenient_decode,
+        topic_name_pattern: config
+            .topic_name_pattern
+            .as_deref()
+            .map(|pattern| regex::Regex::new(pattern).unwrap()),
+        tag_limits: TagLimits {
+            min_chars: config.min_tag_len,
+            max_chars: config.max_tag_chars,
+            max_per_topic: config.max_tags_per_topic,
+        },
+        http: reqwest::Client::new(),
+        tag_blocklist: config.tag_blocklist.iter().map(|t| fold_tag(t)).collect(),
+        github_webhook_secret: config.github_webhook_secret,
+        snapshot_dir: (!config.snapshot_dir.is_empty()).then(|| config.snapshot_dir.into()),
+        response_cache: ResponseCache::new(
+            std::time::Duration::from_secs(config.response_cache_ttl_secs),
+            config.response_cache_max_entries,
+        ),
+        request_slots: (config.max_concurrent_requests > 0)
+            .then(|| Arc::new(tokio::sync::Semaphore::new(config.max_concurrent_requests))),
+        topic_events: tokio::sync::broadcast::channel(256).0,
+    });
+
+    // 后台任务在服务停止后退出
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
+
+    // sled 恢复可能很慢, 先开始服务, 打开后再切换为就绪
+    let db_config = sled::Config::new()
+        .path("data.sled")
+        .cache_capacity(config.cache)
+        .compression_factor(config.compression);
+    tokio::spawn({
+        let state = state.clone();
+        async move {
+            loop {
+                let db_config = db_config.clone();
+                match tokio::task::spawn_blocking(move || db_config.open()).await {
+                    Ok(Ok(db)) => {
+                        // 服务身份必须在数据库对外可见之前写好, 否则 API key 的首批写请求会 404
+                        if let Err(e) = ensure_service_users(&db, &state.api_keys) {
+                            error!("failed to create service users: {e:?}");
+                            std::process::exit(1);
+                        }
+                        if config.flush_interval_secs > 0 {
+                            tokio::spawn(tagme::flush_loop(
+                                db.clone(),
+                                std::time::Duration::from_secs(config.flush_interval_secs),
+                                shutdown_rx.clone(),
+                            ));
+                        }
+                        if config.revocation_sweep_interval_secs > 0 {
+                            tokio::spawn(tagme::revocation_sweep_loop(
+                                db.clone(),
+                                std::time::Duration::from_secs(
+                                    config.revocation_sweep_interval_secs,
+                                ),
+                                shutdown_rx.clone(),
+                            ));
+                        }
+                        let _ = state.db.set(db);
+                        info!("database ready");
+                        break;
+                    }
+                    Ok(Err(e)) => error!("failed to open database: {e}"),
+                    Err(e) => error!("failed to open database: {e}"),
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            }
+        }
     });
 
-    let app = axum::Router::new()
+    let app = nest_base_path(router(state.clone(), cors), &config.base_path);
+
+    let secs = |n: u64| (n > 0).then(|| std::time::Duration::from_secs(n));
+    let options = tagme::ServeOptions {
+        http2: config.http2,
+        http2_keep_alive_interval: secs(config.http2_keep_alive_interval_secs),
+        header_read_timeout: secs(config.header_read_timeout_secs),
+    };
+    tagme::serve(app, 3000, options, tagme::shutdown_signal()).await;
+
+    let _ = shutdown_tx.send(());
+    // 连接都已结束, 最后写一次盘再退出
+    if let Some(db) = state.db.get() {
+        match db.flush_async().await {
+            Ok(bytes) => info!("flushed {bytes} bytes before exit"),
+            Err(e) => error!("failed to flush database: {e}"),
+        }
+    }
+}
+
+fn nest_base_path(app: axum::Router, base_path: &str) -> axum::Router {
+    match base_path.is_empty() {
+        true => app,
+        false => axum::Router::new().nest(base_path, app),
+    }
+}
+
+// 路由和中间件; base_path 由调用方嵌套
+fn router(state: Arc<AppState>, cors: CorsLayer) -> axum::Router {
+    use middleware::from_fn_with_state as mw_fn_with_state;
+
+    axum::Router::new()
+        .route("/health", get(health))
+        .route("/version", get(version))
         .route("/top", get(get_top))
-        .route("/user", get(get_me))
+        .route("/recent", get(get_recent))
+        .route("/topics", get(topics_by_date))
+        .route("/search", get(search_handler))
+        .route("/featured/authors", get(get_featured_authors))
+        .route("/motd", get(get_motd))
+        .route("/events/topics", get(topic_events))
+        .route("/render", post(render_handler))
+        .route("/tags/stats", post(tag_stats_handler))
+        .route("/user", get(get_me).patch(patch_me))
+        .route("/me/export", get(export_me))
+        .route("/me/topics/visibility", post(topics_visibility_handler))
         .route("/user/{*user}", get(get_user).delete(ban_user))
+        .route("/stats", get(site_stats))
+        .route("/stats/user/{*user}", get(user_stats))
         .route(
             "/topic/{*topic}",
             get(get_topic).post(topic_handler).delete(del_topic),
         )
         .route("/add/tag/{*topic}", post(add_tags_handler))
         .route("/del/tag/{*topic}", post(del_tags_handler))
+        .route("/del/vote/{*topic}", post(unvote_handler))
+        .route("/approve-all/tag/{*topic}", post(approve_all_handler))
+        .route("/import/tag/{*topic}", post(import_tags_handler))
+        .route("/reject-all/tag/{*topic}", post(reject_all_handler))
+        .route("/policy/tag/{*topic}", post(tag_policy_handler))
+        .route("/move/tag/{*topic}", post(move_tag_handler))
+        // 以下不改 tag, 路径用 /topic/
+        .route("/voted/topic/{*topic}", get(voted_handler))
+        .route("/pending/topic/{*topic}", get(pending_handler))
+        .route("/claim/topic/{*topic}", post(claim_handler))
+        .route("/tag-history/topic/{*topic}", get(tag_history_handler))
+        .route("/timeseries/topic/{*topic}", get(tag_timeseries_handler))
+        .route("/author-topics/topic/{*topic}", get(author_topics_handler))
+        .route("/similar/topic/{*topic}", get(similar_topics_handler))
+        .route("/lock/topic/{*topic}", post(lock_handler))
+        .route("/unlock/topic/{*topic}", post(unlock_handler))
+        .route("/token/embed", post(embed_token_handler))
+        .route("/token/refresh", get(refresh_token_handler))
+        .route("/oauth/login", get(oauth_login))
+        .route("/logout", post(logout))
         .route("/oauth/callback", get(oauth_callback))
-        .route("/private/admin", get(admin_handler))
+        .route("/oauth/github/webhook", post(github_webhook))
+        .route("/private/admin", post(admin_handler))
+        .route("/private/admin/rotate-key", post(rotate_key_handler))
+        .route("/private/admin/roles", post(roles_handler))
+        .route("/private/admin/config", get(config_handler))
+        .route("/private/admin/tag-synonym", post(tag_synonym_handler))
+        .route("/private/admin/motd", post(motd_handler))
+        .route("/private/admin/prune", post(prune_handler))
+        .route(
+            "/private/admin/feature/author/{*user}",
+            post(feature_author_handler),
+        )
+        .route(
+            "/private/admin/unfeature/author/{*user}",
+            post(unfeature_author_handler),
+        )
+        .route("/private/admin/raw", get(raw_handler))
+        .route("/private/admin/merge-users", post(merge_users_handler))
+        .route("/private/admin/snapshot", post(snapshot_handler))
+        .route("/private/admin/integrity", get(integrity_handler))
+        .route("/private/admin/duplicates", get(duplicates_handler))
         .route("/uwu", get(async || "Kemi Amu: uwu"))
         .fallback(async || StatusCode::BAD_REQUEST)
         //
-        .layer(mw_fn(tagme::token::token_middleware))
-        .layer(tower_http::trace::TraceLayer::new_for_http())
-        .with_state(state);
+        .layer(mw_fn_with_state(
+            state.clone(),
+            tagme::cache::cache_middleware,
+        ))
+        .layer(middleware::from_fn(tagme::pretty_json_middleware))
+        .layer(mw_fn_with_state(
+            state.clone(),
+            tagme::rate::rate_limit_middleware,
+        ))
+        .layer(mw_fn_with_state(
+            state.clone(),
+            tagme::rate::rate_headers_middleware,
+        ))
+        .layer(mw_fn_with_state(
+            state.clone(),
+            tagme::token::token_middleware,
+        ))
+        .layer(mw_fn_with_state(state.clone(), tagme::ready_middleware))
+        .layer(mw_fn_with_state(state.clone(), tagme::load_shed_middleware))
+        .layer(cors)
+        .layer(tower_http::trace::TraceLayer::new_for_http().make_span_with(tagme::make_span))
+        .layer(middleware::from_fn(tagme::request_id_middleware))
+        .with_state(state)
+}
+
+// 给 /private/admin/config 用, 密钥一律替换为占位符
+fn redact_config(mut config: serde_json::Value) -> serde_json::Value {
+    const REDACTED: &str = "[redacted]";
+    for key in [
+        "github_oauth_client_secrets",
+        "github_webhook_secret",
+        "token_secret",
+    ] {
+        if config[key].as_str().is_some_and(|s| !s.is_empty()) {
+            config[key] = json!(REDACTED);
+        }
+    }
+    if let Some(keys) = config["read_api_keys"].as_array_mut() {
+        keys.iter_mut().for_each(|k| *k = json!(REDACTED));
+    }
+    // key -> uid, 只保留服务 uid
+    if let Some(keys) = config["write_api_keys"].as_object() {
+        config["write_api_keys"] = json!(keys.values().collect::<Vec<_>>());
+    }
+    config
+}
+
+// 凭据模式下规范不允许通配 origin, 直接拒绝启动
+fn cors_layer(origins: &[String], credentials: bool, max_age_secs: u64) -> CorsLayer {
+    use axum::http::{HeaderName, HeaderValue, Method};
+    let wildcard: bool = origins.iter().any(|o| o == "*");
+    assert!(
+        !(wildcard && credentials),
+        "cors_allow_credentials cannot be used with a wildcard origin"
+    );
+    let (patterns, exact): (Vec<String>, Vec<String>) = origins
+        .iter()
+        .cloned()
+        .partition(|o| o.contains('*') && o != "*");
+    for pattern in &patterns {
+        assert!(
+            is_safe_origin_pattern(pattern),
+            "invalid CORS origin pattern '{pattern}', only a leading '*.' under a registrable domain is allowed"
+        );
+    }
+    let allow_origin: AllowOrigin = match (wildcard, patterns.is_empty()) {
+        (true, _) => AllowOrigin::any(),
+        (false, true) => AllowOrigin::list(
+            exact
+                .iter()
+                .map(|o| HeaderValue::from_str(o).expect("invalid CORS origin")),
+        ),
+        // 有通配子域时逐个匹配; 精确项 (含 "null") 照旧比较
+        (false, false) => AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+            let Ok(origin) = origin.to_str() else {
+                return false;
+            };
+            exact.iter().any(|o| o == origin) || patterns.iter().any(|p| origin_matches(p, origin))
+        }),
+    };
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::IF_MATCH,
+            header::IF_NONE_MATCH,
+            HeaderName::from_static("x-api-key"),
+            HeaderName::from_static("x-embed-token"),
+            HeaderName::from_static(tagme::token::CSRF_HEADER),
+        ])
+        .expose_headers([
+            header::AUTHORIZATION,
+            header::ETAG,
+            header::RETRY_AFTER,
+            HeaderName::from_static("x-request-id"),
+            HeaderName::from_static("x-total-count"),
+            HeaderName::from_static("x-ratelimit-limit"),
+            HeaderName::from_static("x-ratelimit-remaining"),
+            HeaderName::from_static("x-ratelimit-reset"),
+        ])
+        .allow_credentials(credentials)
+        .max_age(std::time::Duration::from_secs(max_age_secs))
+}
 
-    tagme::serve(app, 3000).await;
+// 只允许 [scheme://]*.a.b 这种形式, 拒绝 *.com, https://* 之类过宽的写法
+fn is_safe_origin_pattern(pattern: &str) -> bool {
+    let host: &str = pattern.split_once("://").map_or(pattern, |(_, host)| host);
+    let Some(suffix) = host.strip_prefix("*.") else {
+        return false;
+    };
+    !suffix.contains('*') && !suffix.contains('/') && suffix.split('.').count() >= 2
+}
+
+// *.example.com 匹配任意深度的子域, 但不匹配 example.com 本身; 端口需写在模式里
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let (scheme, host_pattern): (Option<&str>, &str) = match pattern.split_once("://") {
+        Some((scheme, host)) => (Some(scheme), host),
+        None => (None, pattern),
+    };
+    let Some((origin_scheme, origin_host)) = origin.split_once("://") else {
+        return false;
+    };
+    let suffix: &str = &host_pattern[1..]; // 去掉 '*', 保留前导 '.'
+    scheme.is_none_or(|s| s.eq_ignore_ascii_case(origin_scheme))
+        && origin_host.len() > suffix.len()
+        && origin_host
+            .to_ascii_lowercase()
+            .ends_with(&suffix.to_ascii_lowercase())
+        && origin_host[..origin_host.len() - suffix.len()]
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+}
+
+// 写 API key 的服务身份需要一个用户记录才能创建 topic
+fn ensure_service_users(
+    db: &sled::Db,
+    api_keys: &HashMap<String, ApiScope>,
+) -> Result<(), AppError> {
+    with_transaction(db, |helper| {
+        for scope in api_keys.values() {
+            if let ApiScope::Write(uid) = scope
+                && helper.get::<_, UserStatus>(uid)?.is_none()
+            {
+                let mut user_status = UserStatus::default();
+                user_status.data_mut().login = format!("service-{uid}");
+                user_status.data_mut().created_at = 0; // 服务身份不算新账号
+                helper.insert(uid, &user_status)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+async fn health(State(state): State<Arc<AppState>>) -> StatusCode {
+    match state.is_ready() {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+async fn version() -> Json<serde_json::Value> {
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("TAGME_GIT_SHA"),
+        "build_time": env!("TAGME_BUILD_TIME"),
+        "rustc": env!("TAGME_RUSTC"),
+    }))
 }
 
 // top
 
+#[derive(Serialize)]
+struct TopDetail {
+    name: String,
+    description: String,
+    tag_total: u64,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    author: Option<TopAuthor>,
+}
+
+impl TopDetail {
+    fn new(name: String, topic: Topic, author: Option<TopAuthor>) -> Self {
+        Self {
+            name,
+            tag_total: topic.tags.values().map(|&c| c as u64).sum(),
+            description: topic.description,
+            author,
+        }
+    }
+}
+
+// 作者已删除时两项均为 null
+#[derive(Serialize, Clone)]
+struct TopAuthor {
+    author_login: Option<String>,
+    author_avatar_url: Option<String>,
+}
+
+// ?detail=true 时附带描述和票数, 再加 &authors=true 附带作者; ?tag= 过滤
 async fn get_top(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<String>>, (StatusCode, &'static str)> {
-    with_transaction(&state.db, |helper| {
+    Query(query): Query<HashMap<String, String>>,
+    page: Pagination,
+) -> Result<impl IntoResponse, AppError> {
+    let detail: bool = query.get("detail").is_some_and(|v| v == "true");
+    let authors: bool = query.get("authors").is_some_and(|v| v == "true");
+    // 响应仍是数组, 总数放在 X-Total-Count 里, 不破坏现有客户端
+    let (total, body) = state.transaction(|helper| {
+        let mut names: Vec<String> = helper.get::<_, Top>(&"")?.unwrap_or_default().0;
+        // ?tag= 只保留带该 tag 的 topic; 没有 tag 索引, 只能逐个检查
+        if let Some(tag) = query.get("tag") {
+            let tag: String = canonical_tag(&helper, tag)?;
+            let topics: Vec<Option<Topic>> = helper.get_many(&names)?;
+            names = names
+                .into_iter()
+                .zip(topics)
+                .filter(|(_, topic)| topic.as_ref().is_some_and(|t| t.tags.contains_key(&tag)))
+                .map(|(name, _)| name)
+                .collect();
+        }
+        let total: usize = names.len();
+        let names: Vec<String> = page.apply(names);
+        if !detail {
+            return Ok((total, json!(names)));
+        }
+        let topics: Vec<(String, Topic)> = helper
+            .get_many::<_, Topic>(&names)?
+            .into_iter()
+            .zip(names)
+            .filter_map(|(topic, name)| Some((name, topic?)))
+            .collect();
+
+        // 每个作者只查一次
+        let mut author_map: HashMap<u64, TopAuthor> = HashMap::new();
+        if authors {
+            let uids: Vec<u64> = topics
+                .iter()
+                .map(|(_, topic)| topic.author)
+                .collect::<HashSet<u64>>()
+                .into_iter()
+                .collect();
+            for (uid, status) in uids.iter().zip(helper.get_many::<_, UserStatus>(&uids)?) {
+                let data: Option<&UserData> = status.as_ref().map(UserStatus::data);
+                author_map.insert(
+                    *uid,
+                    TopAuthor {
+                        author_login: data.map(|d| d.login.clone()),
+                        author_avatar_url: data.map(|d| d.display_avatar().to_string()),
+                    },
+                );
+            }
+        }
+
+        let details: Vec<TopDetail> = topics
+            .into_iter()
+            .map(|(name, topic)| {
+                let author: Option<TopAuthor> = author_map.get(&topic.author).cloned();
+                TopDetail::new(name, topic, author)
+            })
+            .collect();
+        Ok((total, json!(details)))
+    })?;
+    Ok(([("X-Total-Count", total.to_string())], Json(body)))
+}
+
+#[derive(Serialize)]
+struct RecentTopic {
+    name: String,
+    updated_at: i64,
+}
+
+// 按最后修改时间倒序, 直接扫描 top 列表排序
+async fn get_recent(
+    State(state): State<Arc<AppState>>,
+    page: Pagination,
+) -> Result<impl IntoResponse, AppError> {
+    // 与 /top 一样, 总数放在 X-Total-Count 里
+    let (total, recent) = state.transaction(|helper| {
         let top: Top = helper.get(&"")?.unwrap_or_default();
-        Ok(Json(top.0))
+        let mut recent: Vec<RecentTopic> = helper
+            .get_many::<_, Topic>(&top.0)?
+            .into_iter()
+            .zip(&top.0)
+            .filter_map(|(topic, name)| {
+                Some(RecentTopic {
+                    name: name.clone(),
+                    updated_at: topic?.updated_at,
+                })
+            })
+            .collect();
+        recent.sort_by_key(|t| std::cmp::Reverse(t.updated_at));
+        Ok((recent.len(), page.apply(recent)))
+    })?;
+    Ok(([("X-Total-Count", total.to_string())], Json(recent)))
+}
+
+// 推荐作者, 跳过已删除和被封禁的
+async fn get_featured_authors(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<UserInfo>>, AppError> {
+    state.transaction(|helper| {
+        let featured: FeaturedAuthors = helper.get(&"")?.unwrap_or_default();
+        let authors: Vec<UserInfo> = helper
+            .get_many::<_, UserStatus>(&featured.0)?
+            .into_iter()
+            .zip(&featured.0)
+            .filter_map(|(user_status, &uid)| Some((user_status.filter(|u| !u.is_banned())?, uid)))
+            .map(|(user_status, uid)| public_info(&helper, user_status, uid))
+            .collect::<Result<_, AppError>>()?;
+        Ok(Json(authors))
     })
 }
 
-// user...
+#[derive(Serialize)]
+struct CreatedTopic {
+    name: String,
+    created_at: i64,
+}
 
-async fn get_me(
+// ?since=&until= 为 unix 秒或 YYYY-MM-DD (UTC), 两端都包含; 按创建时间升序
+// top 列表基本按创建顺序排列, 但没有时间索引, 仍需逐个读取
+async fn topics_by_date(
     State(state): State<Arc<AppState>>,
     Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
-) -> Result<Json<UserInfo>, (StatusCode, &'static str)> {
-    let uid: u64 = token.lock().await.auth()?;
-    with_transaction(&state.db, |helper| {
-        let user_status: UserStatus = helper.get_or_not_found(&uid)?;
-        Ok(Json(user_status.into_info(uid)))
+    Query(query): Query<HashMap<String, String>>,
+    page: Pagination,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let bound = |key: &str, end_of_day: bool| -> Result<Option<i64>, AppError> {
+        query
+            .get(key)
+            .map(|s| {
+                parse_time(s, end_of_day)
+                    .ok_or(FieldError::new(key, "invalid_time", "Invalid time").into())
+            })
+            .transpose()
+    };
+    let since: i64 = bound("since", false)?.unwrap_or(i64::MIN);
+    let until: i64 = bound("until", true)?.unwrap_or(i64::MAX);
+    if since > until {
+        return Err(FieldError::new("since", "invalid_range", "since is after until").into());
+    }
+    let uid: Option<u64> = token.lock().await.get_sub();
+
+    state.transaction(|helper| {
+        let mut names: Vec<String> = helper.get::<_, Top>(&"")?.unwrap_or_default().0;
+        // 登录用户还能看到自己的私有 topic
+        if let Some(user_status) = uid
+            .map(|uid| helper.get::<_, UserStatus>(&uid))
+            .transpose()?
+            .flatten()
+        {
+            let listed: HashSet<String> = names.iter().cloned().collect();
+            names.extend(
+                user_status
+                    .into_data()
+                    .topics
+                    .into_iter()
+                    .filter(|name| !listed.contains(name)),
+            );
+        }
+        let mut topics: Vec<CreatedTopic> = helper
+            .get_many::<_, Topic>(&names)?
+            .into_iter()
+            .zip(names)
+            .filter_map(|(topic, name)| {
+                let topic: Topic = topic?;
+                let visible: bool = !topic.is_private() || Some(topic.author) == uid;
+                (visible && (since..=until).contains(&topic.created_at)).then_some(CreatedTopic {
+                    name,
+                    created_at: topic.created_at,
+                })
+            })
+            .collect();
+        topics.sort_by(|a, b| {
+            a.created_at
+                .cmp(&b.created_at)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        Ok(Json(json!({
+            "total": topics.len(),
+            "topics": page.apply(topics),
+        })))
     })
 }
 
-async fn get_user(
+// unix 秒, 或 YYYY-MM-DD 取当天开始 / 结束
+fn parse_time(s: &str, end_of_day: bool) -> Option<i64> {
+    if let Ok(secs) = s.parse::<i64>() {
+        return Some(secs);
+    }
+    let mut parts = s.splitn(3, '-').map(str::parse::<u32>);
+    let (year, month, day) = (
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    );
+    let month = time::Month::try_from(u8::try_from(month).ok()?).ok()?;
+    let date = time::Date::from_calendar_date(year as i32, month, u8::try_from(day).ok()?).ok()?;
+    let start: i64 = date.midnight().as_utc().unix_timestamp();
+    Some(match end_of_day {
+        true => start + 86_399,
+        false => start,
+    })
+}
+
+// search
+
+async fn search_handler(
     State(state): State<Arc<AppState>>,
-    Path(uid): Path<u64>,
-) -> Result<Json<UserInfo>, (StatusCode, &'static str)> {
-    with_transaction(&state.db, |helper| {
-        let user_status: UserStatus = helper.get_or_not_found(&uid)?;
-        Ok(Json(user_status.into_info(uid)))
+    Query(query): Query<HashMap<String, String>>,
+    page: Pagination,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let q = query
+        .get("q")
+        .ok_or((StatusCode::BAD_REQUEST, "No query"))?;
+    let sort: search::Sort = query
+        .get("sort")
+        .map_or(Ok(search::Sort::Relevance), |s| s.parse())
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                "unsupported_sort",
+                "Unsupported sort",
+            )
+        })?;
+    state.transaction(|helper| {
+        let topics: Vec<String> = search::search_sorted(&helper, q, sort)?;
+        Ok(Json(json!({
+            "total": topics.len(),
+            "topics": page.apply(topics),
+        })))
     })
 }
 
-async fn ban_user(
+#[derive(Deserialize)]
+struct RenderPost {
+    markdown: String,
+}
+
+// 预览描述渲染后的 HTML, 不落库
+async fn render_handler(
+    AppJson(post): AppJson<RenderPost>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    validate_description(&post.markdown).map_err(|e| e.for_field("markdown"))?;
+    Ok(Json(json!({
+        "html": tagme::render::render_markdown(&post.markdown),
+    })))
+}
+
+#[derive(Deserialize)]
+struct TagStatsPost {
+    tags: Vec<String>,
+}
+
+#[derive(Serialize, Default, Clone)]
+struct TagStats {
+    topics: u64,
+    total: u64,
+}
+
+// 没有全局 tag 统计, 扫描所有 topic 汇总
+async fn tag_stats_handler(
     State(state): State<Arc<AppState>>,
-    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
-    Path(uid): Path<u64>,
-) -> Result<StatusCode, (StatusCode, &'static str)> {
-    let admin_uid: u64 = token.lock().await.auth()?;
-    with_transaction(&state.db, |helper| {
-        let admin_status: UserStatus = helper.get_or_not_found(&admin_uid)?;
-        if !admin_status.is_admin() {
-            return Err((StatusCode::FORBIDDEN, "No, Fuck You!"));
-        }
+    AppJson(post): AppJson<TagStatsPost>,
+) -> Result<Json<HashMap<String, TagStats>>, AppError> {
+    if post.tags.len() > 100 {
+        return Err(FieldError::new("tags", "too_many_tags", "Too many tags").into());
+    }
+    let canonical: Vec<(String, String)> = state.transaction(|helper| {
+        post.tags
+            .iter()
+            .map(|tag| Ok((tag.clone(), canonical_tag(&helper, tag)?)))
+            .collect()
+    })?;
 
-        let mut user_status: UserStatus = helper.get_or_not_found(&uid)?;
-        user_status = UserStatus::Banned(user_status.into_data());
-        helper.insert(&uid, &user_status)?;
-        Ok(StatusCode::OK)
-    })
+    let mut stats: HashMap<String, TagStats> = canonical
+        .iter()
+        .map(|(_, tag)| (tag.clone(), TagStats::default()))
+        .collect();
+    for (_, topic) in state.scan_all::<Topic>()? {
+        if topic.is_private() {
+            continue;
+        }
+        for (tag, count) in &topic.tags {
+            if let Some(entry) = stats.get_mut(tag) {
+                entry.topics += 1;
+                entry.total += *count as u64;
+            }
+        }
+    }
+    // 按请求里的原始写法返回
+    Ok(Json(
+        canonical
+            .into_iter()
+            .map(|(raw, tag)| (raw, stats[&tag].clone()))
+            .collect(),
+    ))
 }
 
-// topics...
+// motd
 
-#[derive(Serialize)]
-struct TopicRes {
-    description: String,
-    author: String,
-    tags: HashMap<String, u32>,
-    pending_tags: HashSet<String>,
+async fn get_motd(State(state): State<Arc<AppState>>) -> Result<Json<Option<Motd>>, AppError> {
+    state.transaction(|helper| Ok(Json(helper.get(&"")?)))
 }
 
-async fn get_topic(
+// user...
+
+async fn get_me(
     State(state): State<Arc<AppState>>,
     Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
-    Path(topic): Path<String>,
-) -> Result<Response<Body>, (StatusCode, &'static str)> {
-    let uid: Option<u64> = token.lock().await.get_sub();
-    with_transaction(&state.db, |helper| {
-        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    page: Pagination,
+) -> Result<Response<Body>, AppError> {
+    let uid: u64 = token.lock().await.auth()?;
+    let Json(value) = state.transaction(|helper| {
+        let user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        user_info_json(
+            &helper,
+            user_status.into_info(uid).page_topics(&page),
+            &query,
+        )
+    })?;
+    let mut response = conditional_json("me", &headers, value);
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("private"));
+    Ok(response)
+}
 
-        let user_status: Option<UserStatus> =
-            uid.map(|uid| helper.get_or_not_found(&uid)).transpose()?;
-        let user_zipped: Option<(u64, &UserStatus)> = uid.zip(user_status.as_ref());
-        let is_owner: bool = user_zipped.map_or(false, |(uid, s)| {
-            s.verified_data(uid, topic_data.author).is_ok()
+// ETag 取自响应内容, scope 区分 /user 和 /user/{uid}, 使两者的缓存不会串用
+fn conditional_json(scope: &str, headers: &HeaderMap, value: serde_json::Value) -> Response<Body> {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(serde_json::to_vec(&value).unwrap_or_default());
+    let hex: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+    let etag = format!("\"{scope}-{hex}\"");
+    let matched: bool = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == "*" || t == etag)
         });
+    match matched {
+        true => (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response(),
+        false => ([(header::ETAG, etag)], Json(value)).into_response(),
+    }
+}
 
-        Ok(Json(TopicRes {
-            description: topic_data.description,
-            author: topic_data.author.to_string(),
-            tags: topic_data.tags,
-            pending_tags: is_owner
-                .then(|| topic_data.pending_tags)
-                .unwrap_or_default(),
-        })
-        .into_response())
-    })
+// ?topics_detail=true 时 topics 换成 { name, description, tag_total }, 跳过已删除的
+fn user_info_json(
+    helper: &DbHelper<'_>,
+    info: UserInfo,
+    query: &HashMap<String, String>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let mut value: serde_json::Value = json!(info);
+    if query.get("topics_detail").is_some_and(|v| v == "true") {
+        let names: &[String] = info.topics();
+        let details: Vec<TopDetail> = helper
+            .get_many::<_, Topic>(names)?
+            .into_iter()
+            .zip(names)
+            .filter_map(|(topic, name)| Some(TopDetail::new(name.clone(), topic?, None)))
+            .collect();
+        value["topics"] = json!(details);
+    }
+    Ok(Json(value))
 }
 
 #[derive(Deserialize)]
-struct TopicPost {
-    description: String,
+struct UserPatch {
+    avatar_override: Option<String>, // 空字符串清除
 }
 
-async fn topic_handler(
+async fn patch_me(
     State(state): State<Arc<AppState>>,
     Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
-    Path(topic): Path<String>,
-    Json(post): Json<TopicPost>,
-) -> Result<Json<TopicRes>, (StatusCode, &'static str)> {
-    if topic.is_empty() || topic.len() > 128 {
-        return Err((StatusCode::BAD_REQUEST, "Topic is invalid"));
+    AppJson(patch): AppJson<UserPatch>,
+) -> Result<Json<UserInfo>, AppError> {
+    if let Some(url) = patch.avatar_override.as_deref().filter(|u| !u.is_empty()) {
+        validate_url(url).map_err(|e| e.for_field("avatar_override"))?;
     }
     let uid: u64 = token.lock().await.auth()?;
-
-    with_transaction(&state.db, |helper| {
+    state.transaction(|helper| {
         let mut user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        if let Some(url) = &patch.avatar_override {
+            user_status.active_data_mut()?.avatar_override =
+                Some(url.clone()).filter(|u| !u.is_empty());
+        }
+        helper.insert(&uid, &user_status)?;
+        Ok(Json(user_status.into_info(uid)))
+    })
+}
 
-        if let Some(mut topic_data) = helper.get::<_, Topic>(&topic)? {
-            user_status.verified_data(uid, topic_data.author)?;
-            topic_data.description = post.description.clone();
-            helper.insert(&topic, &topic_data)?;
-            Ok(Json(TopicRes {
-                description: topic_data.description,
-                author: topic_data.author.to_string(),
-                tags: topic_data.tags,
-                pending_tags: topic_data.pending_tags,
-            }))
-        } else {
-            let user: &mut UserData = user_status.active_data_mut()?;
-            let topic_data = Topic {
-                author: uid,
-                description: post.description.clone(),
-                tags: HashMap::new(),
-                pending_tags: HashSet::new(),
-            };
-            helper.insert(&topic, &topic_data)?;
-
-            let mut top: Top = helper.get(&"")?.unwrap_or_default();
-            top.0.push(topic.clone());
-            helper.insert(&"", &top)?;
+// 导出里的 topic, 不含 voters (其他用户的投票记录)
+#[derive(Serialize)]
+struct TopicExport {
+    description: String,
+    #[serde(serialize_with = "serialize_tags")]
+    tags: HashMap<String, u32>,
+    #[serde(serialize_with = "serialize_sorted")]
+    pending_tags: HashSet<String>,
+    created_at: i64,
+    updated_at: i64,
+    locked: bool,
+    #[serde(serialize_with = "serialize_sorted")]
+    allowed_tags: HashSet<String>,
+    #[serde(serialize_with = "serialize_sorted")]
+    denied_tags: HashSet<String>,
+    url: Option<String>,
+    version: u64,
+    visibility: Visibility,
+}
 
-            user.topics.push(topic.clone());
-            helper.insert(&uid, &user_status)?;
-            Ok(Json(TopicRes {
-                description: topic_data.description,
-                author: topic_data.author.to_string(),
-                tags: topic_data.tags,
-                pending_tags: topic_data.pending_tags,
-            }))
+impl From<Topic> for TopicExport {
+    fn from(topic: Topic) -> Self {
+        Self {
+            description: topic.description,
+            tags: topic.tags,
+            pending_tags: topic.pending_tags,
+            created_at: topic.created_at,
+            updated_at: topic.updated_at,
+            locked: topic.locked,
+            allowed_tags: topic.allowed_tags,
+            denied_tags: topic.denied_tags,
+            url: topic.url,
+            version: topic.version,
+            visibility: topic.visibility,
         }
-    })
+    }
 }
 
-async fn del_topic(
+// 导出自己的全部数据: 资料 (不含 access_token), 自己的 topic, 以及自己投过票的 tag.
+// 投票要扫描全部 topic, 所以逐条读取, 边读边写
+async fn export_me(
     State(state): State<Arc<AppState>>,
     Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
-    Path(topic): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+) -> Result<impl IntoResponse, AppError> {
     let uid: u64 = token.lock().await.auth()?;
-    with_transaction(&state.db, |helper| {
-        let topic_data: Topic = helper.get_or_not_found(&topic)?;
-        let mut user_status: UserStatus = helper.get_or_not_found(&uid)?;
+    let user_status: UserStatus = state.transaction(|helper| helper.get_or_not_found(&uid))?;
+    let names: Vec<String> = user_status.data().topics.clone();
+    let head: String = format!(
+        "{{\"user\":{},\"topics\":{{",
+        json!(user_status.into_info(uid))
+    );
 
-        let user: &mut UserData = user_status.verified_data_mut(uid, topic_data.author)?;
-        user.topics.retain(|t| t != &topic);
-        helper.insert(&uid, &user_status)?;
+    let (db, lenient): (sled::Db, bool) = (state.db().clone(), state.lenient_decode);
+    let topics = names.into_iter().filter_map(move |name| {
+        let topic: Option<Topic> = match tagme::models::get_one(&db, &name, lenient) {
+            Ok(topic) => topic,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(Ok((name, json!(TopicExport::from(topic?)))))
+    });
+    // 别人的私有 topic 不出现在导出里
+    let votes = state.scan_iter::<Topic>().filter_map(move |entry| {
+        let (key, topic) = match entry {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e)),
+        };
+        if topic.is_private() && topic.author != uid {
+            return None;
+        }
+        let mut tags: Vec<&String> = topic
+            .voters
+            .iter()
+            .filter(|(_, voters)| voters.contains(&uid))
+            .map(|(tag, _)| tag)
+            .collect();
+        tags.sort();
+        (!tags.is_empty()).then(|| Ok((String::from_utf8_lossy(&key).into_owned(), json!(tags))))
+    });
 
-        let mut top: Top = helper.get(&"")?.unwrap_or_default();
-        top.0.retain(|t| t != &topic);
-        helper.insert(&"", &top)?;
+    let chunks = std::iter::once(Ok(head))
+        .chain(json_object_entries(topics))
+        .chain(std::iter::once(Ok("},\"votes\":{".to_string())))
+        .chain(json_object_entries(votes))
+        .chain(std::iter::once(Ok("}}".to_string())));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"tagme-{uid}.json\""),
+            ),
+        ],
+        Body::from_stream(tokio_stream::iter(chunks)),
+    ))
+}
 
-        helper.remove::<_, Topic>(&topic)?;
-        Ok((StatusCode::SEE_OTHER, [("Location", "/")]))
+// 流式输出 JSON 对象的成员 "key":value, 不含外层花括号; 读取出错时中断响应
+fn json_object_entries(
+    entries: impl Iterator<Item = Result<(String, serde_json::Value), AppError>>,
+) -> impl Iterator<Item = Result<String, std::io::Error>> {
+    entries.enumerate().map(|(i, entry)| {
+        let (key, value) = entry.map_err(|e| std::io::Error::other(format!("{e:?}")))?;
+        let separator: &str = if i == 0 { "" } else { "," };
+        Ok(format!("{separator}{}:{value}", json!(key)))
     })
 }
 
-// tag...
-
 #[derive(Deserialize)]
-struct TagPost {
-    tag: String,
+struct VisibilityPost {
+    names: Vec<String>,
+    visibility: Visibility,
 }
 
-async fn add_tags_handler(
+// 批量切换自己 topic 的可见性, 私有的移出 top; 返回每个名称的结果
+async fn topics_visibility_handler(
     State(state): State<Arc<AppState>>,
     Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
-    Path(topic): Path<String>,
-    Json(post): Json<TagPost>,
-) -> Result<Json<TopicRes>, (StatusCode, &'static str)> {
-    if post.tag.is_empty() || post.tag.len() > 64 {
-        return Err((StatusCode::BAD_REQUEST, "Tag is invalid"));
+    AppJson(post): AppJson<VisibilityPost>,
+) -> Result<Json<HashMap<String, &'static str>>, AppError> {
+    if post.names.len() > 100 {
+        return Err(FieldError::new("names", "too_many_topics", "Too many topics").into());
     }
-    let uid: Option<u64> = token.lock().await.get_sub();
-
-    with_transaction(&state.db, |helper| {
-        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
-        let is_owner: bool = uid.map_or(false, |uid| {
-            helper
-                .get_or_not_found::<_, UserStatus>(&uid)
-                .map_or(false, |s| s.verified_data(uid, topic_data.author).is_ok())
-        });
-
-        if let Some(count) = topic_data.tags.get_mut(&post.tag) {
-            *count += 1;
-        } else if is_owner {
-            topic_data.tags.insert(post.tag.clone(), 1);
-            topic_data.pending_tags.remove(&post.tag);
-        } else {
-            topic_data.pending_tags.insert(post.tag.clone());
+    let uid: u64 = token.lock().await.auth()?;
+    state.transaction(|helper| {
+        let user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        user_status.as_active()?;
+        let mut top: Top = helper.get(&"")?.unwrap_or_default();
+        let mut results: HashMap<String, &'static str> = HashMap::new();
+        for raw in &post.names {
+            let Ok(topic) = state.normalize_topic(raw) else {
+                results.insert(raw.clone(), "invalid");
+                continue;
+            };
+            let Some(mut topic_data) = helper.get::<_, Topic>(&topic)? else {
+                results.insert(raw.clone(), "not_found");
+                continue;
+            };
+            // 只处理自己的 topic, 管理员也不例外
+            if topic_data.author != uid {
+                results.insert(raw.clone(), "skipped");
+                continue;
+            }
+            if topic_data.visibility == post.visibility {
+                results.insert(raw.clone(), "unchanged");
+                continue;
+            }
+            top.0.retain(|t| *t != topic);
+            if post.visibility == Visibility::Public {
+                top.0.push(topic.clone());
+            }
+            topic_data.visibility = post.visibility;
+            helper.insert(&topic, &topic_data)?;
+            results.insert(raw.clone(), "updated");
         }
-
-        helper.insert(&topic, &topic_data)?;
-        Ok(Json(TopicRes {
-            description: topic_data.description,
-            author: topic_data.author.to_string(),
-            tags: topic_data.tags,
-            pending_tags: is_owner
-                .then(|| topic_data.pending_tags)
-                .unwrap_or_default(),
-        }))
+        helper.insert(&"", &top)?;
+        Ok(Json(results))
     })
 }
 
-async fn del_tags_handler(
+async fn get_user(
     State(state): State<Arc<AppState>>,
-    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
-    Path(topic): Path<String>,
-    Json(post): Json<TagPost>,
-) -> Result<Json<serde_json::Value>, (StatusCode, &'static str)> {
-    let uid: u64 = token.lock().await.auth()?;
-    with_transaction(&state.db, |helper| {
-        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
-        helper
-            .get_or_not_found::<_, UserStatus>(&uid)?
-            .verified_data(uid, topic_data.author)?;
+    Path(uid): Path<u64>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    page: Pagination,
+) -> Result<Response<Body>, AppError> {
+    let Json(value) = state.transaction(|helper| {
+        let user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        user_info_json(
+            &helper,
+            public_info(&helper, user_status, uid)?.page_topics(&page),
+            &query,
+        )
+    })?;
+    Ok(conditional_json("user", &headers, value))
+}
 
-        topic_data.tags.remove(&post.tag);
-        topic_data.pending_tags.remove(&post.tag);
-        helper.insert(&topic, &topic_data)?;
-        Ok(Json(json!({
-            "description": topic_data.description,
-            "tags": topic_data.tags,
-            "pending_tags": topic_data.pending_tags,
-        })))
-    })
+// 他人看到的主页不含私有 topic
+fn public_info(
+    helper: &DbHelper<'_>,
+    mut user_status: UserStatus,
+    uid: u64,
+) -> Result<UserInfo, AppError> {
+    let names: Vec<String> = std::mem::take(&mut user_status.data_mut().topics);
+    let topics: Vec<Option<Topic>> = helper.get_many(&names)?;
+    user_status.data_mut().topics = names
+        .into_iter()
+        .zip(topics)
+        .filter(|(_, topic)| !topic.as_ref().is_some_and(Topic::is_private))
+        .map(|(name, _)| name)
+        .collect();
+    Ok(user_status.into_info(uid))
 }
 
-// oauth
+#[derive(Serialize)]
+struct UserStats {
+    topic_count: usize,
+    total_tags_received: u64,
+    most_popular_topic: Option<String>,
+}
 
-async fn oauth_callback(
+async fn user_stats(
     State(state): State<Arc<AppState>>,
-    Query(query): Query<HashMap<String, String>>,
-) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    let code = query
-        .get("code")
-        .ok_or((StatusCode::BAD_REQUEST, "No code"))?;
-    let client = reqwest::Client::new();
+    Path(uid): Path<u64>,
+) -> Result<Json<UserStats>, AppError> {
+    state.transaction(|helper| {
+        let user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        let names: &[String] = &user_status.data().topics;
+        let totals: Vec<(&String, u64)> = names
+            .iter()
+            .zip(helper.get_many::<_, Topic>(names)?)
+            .filter_map(|(name, topic)| {
+                let topic: Topic = topic.filter(|t| !t.is_private())?;
+                Some((name, topic.tags.values().map(|&c| c as u64).sum()))
+            })
+            .collect();
 
-    let resp = client
-        .post("https://github.com/login/oauth/access_token")
-        .header("Accept", "application/json")
-        .json(&json!({
-            "client_id": state.oauth_client_id,
-            "client_secret": state.oauth_client_secrets,
-            "code": code,
+        Ok(Json(UserStats {
+            topic_count: totals.len(),
+            total_tags_received: totals.iter().map(|(_, total)| total).sum(),
+            most_popular_topic: totals
+                .iter()
+                .max_by_key(|(_, total)| *total)
+                .map(|(name, _)| name.to_string()),
         }))
-        .send()
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to request token"))?;
-    let token_data: serde_json::Value = resp
-        .json()
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse token"))?;
-    let access_token: String = token_data["access_token"]
-        .as_str()
-        .ok_or((StatusCode::BAD_REQUEST, "Invalid token response"))?
-        .to_string();
+    })
+}
 
-    let user_resp = client
-        .get("https://api.github.com/user")
-        .bearer_auth(&access_token)
-        .header("User-Agent", "KemiAmu:tagme")
-        .send()
-        .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to request user info",
-            )
-        })?;
-    let user_data: serde_json::Value = user_resp.json().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to parse user info",
+#[derive(Serialize)]
+struct SiteStats {
+    topic_count: usize,
+    tag_count: usize,
+    total_votes: u64,
+    user_count: usize,
+}
+
+// 全站汇总只统计公开 topic; 需要扫描全部数据, 依赖响应缓存挡住重复请求
+async fn site_stats(State(state): State<Arc<AppState>>) -> Result<Json<SiteStats>, AppError> {
+    let mut stats = SiteStats {
+        topic_count: 0,
+        tag_count: 0,
+        total_votes: 0,
+        user_count: state.scan_all::<UserStatus>()?.len(),
+    };
+    let mut tags: HashSet<String> = HashSet::new();
+    for (_, topic) in state.scan_all::<Topic>()? {
+        if topic.is_private() {
+            continue;
+        }
+        stats.topic_count += 1;
+        stats.total_votes += topic.tags.values().map(|&c| c as u64).sum::<u64>();
+        tags.extend(topic.tags.into_keys());
+    }
+    stats.tag_count = tags.len();
+    Ok(Json(stats))
+}
+
+// 吊销该用户所有已签发的 token (所有设备一起登出); 没有 token 也返回 200
+async fn logout(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+) -> Result<Response<Body>, AppError> {
+    let mut token = token.lock().await;
+    // API key 的服务身份没有可吊销的 token
+    if let Some(uid) = token.as_ref().map(|t| t.sub) {
+        state.transaction(|helper| helper.insert(&uid, &Revoked(now())))?;
+    }
+    // 不再回写刷新后的 token
+    *token = OptionalToken::default();
+    match state.cookie_auth {
+        true => Ok((
+            [(header::SET_COOKIE, tagme::token::clear_cookie(&state))],
+            StatusCode::OK,
         )
-    })?;
-    let github_id = user_data["id"]
-        .as_u64()
-        .ok_or((StatusCode::BAD_REQUEST, "Invalid user id"))?;
-    let login = user_data["login"].as_str().unwrap_or("").to_string();
-    let name = user_data["name"].as_str().unwrap_or("").to_string();
-    let avatar_url = user_data["avatar_url"].as_str().unwrap_or("").to_string();
-    let bio = user_data["bio"].as_str().unwrap_or("").to_string();
-    let token = Token::new(github_id);
+            .into_response()),
+        false => Ok(StatusCode::OK.into_response()),
+    }
+}
 
-    with_transaction(&state.db, |helper| {
-        let mut user_status: UserStatus = helper.get(&github_id)?.unwrap_or_default();
-        let user: &mut UserData = user_status.data_mut();
-        user.access_token = access_token.clone();
-        user.login = login.clone();
-        user.name = name.clone();
-        user.avatar_url = avatar_url.clone();
-        user.bio = bio.clone();
-        helper.insert(&github_id, &user_status)?;
+async fn ban_user(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(uid): Path<u64>,
+) -> Result<StatusCode, AppError> {
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    state.transaction(|helper| {
+        let admin_status: UserStatus = helper.get_or_not_found(&admin_uid)?;
+        if !admin_status.is_admin() {
+            return Err((StatusCode::FORBIDDEN, "No, Fuck You!").into());
+        }
 
-        Ok(Html(format!(
-            r#"<!doctype html>
-            <html><head><script>
-            localStorage.setItem("Token", "{}");
-            window.location.href = "/profile";
-            </script></head></html>"#,
-            token.to_string()
-        )))
+        let mut user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        user_status = UserStatus::Banned(user_status.into_data());
+        helper.insert(&uid, &user_status)?;
+        // 已签发的 token 立即失效
+        helper.insert(&uid, &Revoked(now()))?;
+        Ok(StatusCode::OK)
     })
 }
 
-// admin
+// topics...
 
-async fn admin_handler(
+#[derive(Serialize)]
+struct TopicRes {
+    description: String,
+    author: String,
+    #[serde(serialize_with = "serialize_tags")]
+    tags: HashMap<String, u32>,
+    #[serde(serialize_with = "serialize_sorted")]
+    pending_tags: HashSet<String>,
+    locked: bool,
+    url: Option<String>,
+    version: u64,
+    visibility: Visibility,
+}
+
+// 稳定顺序: 票数降序, 同票按名称升序
+fn serialize_tags<S: serde::Serializer>(
+    tags: &HashMap<String, u32>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut tags: Vec<(&String, &u32)> = tags.iter().collect();
+    tags.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    serializer.collect_map(tags)
+}
+
+fn serialize_sorted<S: serde::Serializer>(
+    set: &HashSet<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut set: Vec<&String> = set.iter().collect();
+    set.sort();
+    serializer.collect_seq(set)
+}
+
+impl TopicRes {
+    fn new(topic: Topic, show_pending: bool) -> Self {
+        Self {
+            description: topic.description,
+            author: topic.author.to_string(),
+            tags: topic.tags,
+            pending_tags: match show_pending {
+                true => topic.pending_tags,
+                false => HashSet::new(),
+            },
+            locked: topic.locked,
+            url: topic.url,
+            version: topic.version,
+            visibility: topic.visibility,
+        }
+    }
+}
+
+async fn get_topic(
     State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    EmbedGrant(embed): EmbedGrant,
+    Path(topic): Path<String>,
     Query(query): Query<HashMap<String, String>>,
-) -> Result<(StatusCode, String), (StatusCode, &'static str)> {
-    let uid: u64 = query
-        .get("op")
-        .or_else(|| query.get("deop"))
-        .and_then(|s| s.parse().ok())
-        .ok_or((StatusCode::BAD_REQUEST, "Invalid uid"))?;
-
-    with_transaction(&state.db, |helper| {
+) -> Result<Response<Body>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    if embed.as_ref().is_some_and(|e| e.topic != topic) {
+        return Err((StatusCode::FORBIDDEN, "Embed token is for another topic").into());
+    }
+    // 作者签发的嵌入 token 可以读这个私有 topic
+    let embedded: bool = embed.is_some();
+    let uid: Option<u64> = token.lock().await.get_sub();
+    tagme::record_ctx("get_topic", &topic, uid);
+    state.transaction(|helper| {
+        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+
+        let user_status: Option<UserStatus> =
+            uid.map(|uid| helper.get_or_not_found(&uid)).transpose()?;
+        let user_zipped: Option<(u64, &UserStatus)> = uid.zip(user_status.as_ref());
+        let is_owner: bool =
+            user_zipped.is_some_and(|(uid, s)| s.verified_data(uid, topic_data.author).is_ok());
+        if topic_data.is_private() && !is_owner && !embedded {
+            return Err(AppError::not_found_named(Topic::name(), &topic));
+        }
+
+        let etag = [(header::ETAG, topic_data.etag())];
+        let res = TopicRes::new(topic_data, is_owner);
+        match query.get("fields") {
+            Some(fields) => Ok((etag, Json(select_fields(&res, fields)?)).into_response()),
+            None => Ok((etag, Json(res)).into_response()),
+        }
+    })
+}
+
+// 同一作者的其他 topic; 作者本人能看到私有的, 作者已删除时为空
+async fn author_topics_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    page: Pagination,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: Option<u64> = token.lock().await.get_sub();
+    state.transaction(|helper| {
+        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+        let is_author: bool = uid == Some(topic_data.author);
+        if topic_data.is_private() && !is_author {
+            return Err(AppError::not_found_named(Topic::name(), &topic));
+        }
+        let names: Vec<String> = helper
+            .get::<_, UserStatus>(&topic_data.author)?
+            .map(|s| s.into_data().topics)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|name| *name != topic)
+            .collect();
+        let others: Vec<TopDetail> = helper
+            .get_many::<_, Topic>(&names)?
+            .into_iter()
+            .zip(names)
+            .filter_map(|(other, name)| Some((name, other?)))
+            .filter(|(_, other)| is_author || !other.is_private())
+            .map(|(name, other)| TopDetail::new(name, other, None))
+            .collect();
+        Ok(Json(json!({
+            "total": others.len(),
+            "topics": page.apply(others),
+        })))
+    })
+}
+
+#[derive(Serialize)]
+struct SimilarTopic {
+    name: String,
+    similarity: f64, // tag 集合的 Jaccard 系数
+}
+
+// 没有 tag 到 topic 的反向索引, 只在 top 列表最近的 MAX_CANDIDATES 个里找
+async fn similar_topics_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    page: Pagination,
+) -> Result<Json<serde_json::Value>, AppError> {
+    const MAX_CANDIDATES: usize = 2000;
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: Option<u64> = token.lock().await.get_sub();
+    state.transaction(|helper| {
+        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+        if topic_data.is_private() && uid != Some(topic_data.author) {
+            return Err(AppError::not_found_named(Topic::name(), &topic));
+        }
+        let tags: HashSet<&String> = topic_data.tags.keys().collect();
+
+        let top: Top = helper.get(&"")?.unwrap_or_default();
+        let candidates: Vec<String> = top
+            .0
+            .into_iter()
+            .rev()
+            .filter(|name| *name != topic)
+            .take(MAX_CANDIDATES)
+            .collect();
+        let mut similar: Vec<SimilarTopic> = helper
+            .get_many::<_, Topic>(&candidates)?
+            .into_iter()
+            .zip(candidates)
+            .filter_map(|(other, name)| {
+                let other: Topic = other.filter(|t| !t.is_private())?;
+                let shared: usize = other.tags.keys().filter(|t| tags.contains(t)).count();
+                let union: usize = tags.len() + other.tags.len() - shared;
+                (shared > 0).then(|| SimilarTopic {
+                    name,
+                    similarity: shared as f64 / union as f64,
+                })
+            })
+            .collect();
+        similar.sort_by(|a, b| {
+            b.similarity
+                .total_cmp(&a.similarity)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        Ok(Json(json!({
+            "total": similar.len(),
+            "topics": page.apply(similar),
+        })))
+    })
+}
+
+// 带 If-Match 时必须与当前 ETag 一致, 否则 412
+fn check_if_match(headers: &HeaderMap, topic_data: &Topic) -> Result<(), AppError> {
+    let Some(if_match) = headers.get(header::IF_MATCH) else {
+        return Ok(());
+    };
+    let etag = topic_data.etag();
+    let matched = if_match
+        .to_str()
+        .is_ok_and(|v| v.split(',').map(str::trim).any(|t| t == "*" || t == etag));
+    match matched {
+        true => Ok(()),
+        false => Err((
+            StatusCode::PRECONDITION_FAILED,
+            "topic_changed",
+            "Topic has changed",
+        )
+            .into()),
+    }
+}
+
+// 锁定的 topic 只有管理员能改 tag, 否则 423
+fn check_unlocked(
+    helper: &DbHelper<'_>,
+    topic_data: &Topic,
+    uid: Option<u64>,
+) -> Result<(), AppError> {
+    if !topic_data.locked {
+        return Ok(());
+    }
+    let is_admin: bool = match uid {
+        Some(uid) => helper
+            .get::<_, UserStatus>(&uid)?
+            .is_some_and(|s| s.is_admin()),
+        None => false,
+    };
+    match is_admin {
+        true => Ok(()),
+        false => Err((StatusCode::LOCKED, "topic_locked", "Topic is locked").into()),
+    }
+}
+
+// ?fields=a,b 只返回所选字段, 未知字段 400
+fn select_fields<T: Serialize>(value: &T, fields: &str) -> Result<serde_json::Value, AppError> {
+    let mut value = serde_json::to_value(value)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Serialize failed"))?;
+    let map = value
+        .as_object_mut()
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Serialize failed"))?;
+
+    let fields: HashSet<&str> = fields.split(',').map(str::trim).collect();
+    if !fields.iter().all(|f| map.contains_key(*f)) {
+        return Err((StatusCode::BAD_REQUEST, "unknown_field", "Unknown field").into());
+    }
+    map.retain(|k, _| fields.contains(k.as_str()));
+    Ok(value)
+}
+
+#[derive(Deserialize)]
+struct TopicPost {
+    description: String,
+    tags: Option<Vec<String>>, // 仅创建时生效
+    url: Option<String>,       // 省略则不变, 空字符串清除
+    expected_version: Option<u64>,
+}
+
+impl Validate for TopicPost {
+    fn validate(&self, state: &AppState, errors: &mut Vec<FieldError>) {
+        if let Err(e) = validate_description(&self.description) {
+            errors.push(e);
+        }
+        let tags: &[String] = self.tags.as_deref().unwrap_or_default();
+        if tags.len() > state.tag_limits.max_per_topic {
+            errors.push(FieldError::new("tags", "too_many_tags", "Too many tags"));
+        }
+        for (i, tag) in tags.iter().enumerate() {
+            if let Err(e) = state.tag_limits.validate_tag(tag) {
+                errors.push(e.for_field(format!("tags[{i}]")));
+            }
+        }
+        if let Some(url) = self.url.as_deref().filter(|u| !u.is_empty())
+            && let Err(e) = validate_url(url)
+        {
+            errors.push(e);
+        }
+    }
+}
+
+async fn topic_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    headers: HeaderMap,
+    Path(topic): Path<String>,
+    AppJson(post): AppJson<TopicPost>,
+) -> Result<Json<TopicRes>, AppError> {
+    // 路径和请求体的字段错误一起返回
+    let mut errors: Vec<FieldError> = Vec::new();
+    let topic: String = state.normalize_topic(&topic).unwrap_or_else(|e| {
+        errors.push(e);
+        String::new()
+    });
+    post.validate(&state, &mut errors);
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+    let initial_tags: &[String] = post.tags.as_deref().unwrap_or_default();
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx("edit_topic", &topic, Some(uid));
+
+    let (res, created) = state.transaction(|helper| {
         let mut user_status: UserStatus = helper.get_or_not_found(&uid)?;
-        match query.contains_key("op") {
-            true => {
-                user_status = UserStatus::Admin(user_status.into_data());
-                helper.insert(&uid, &user_status)?;
-                Ok((
-                    StatusCode::OK,
-                    format!("User @{} promoted to admin\n", user_status.data().login),
-                ))
+
+        if let Some(mut topic_data) = helper.get::<_, Topic>(&topic)? {
+            user_status.verified_data(uid, topic_data.author)?;
+            check_if_match(&headers, &topic_data)?;
+            if post
+                .expected_version
+                .is_some_and(|v| v != topic_data.version)
+            {
+                return Err((
+                    StatusCode::CONFLICT,
+                    "version_conflict",
+                    "Topic version has changed",
+                )
+                    .into());
             }
-            false => {
-                user_status = UserStatus::Normal(user_status.into_data());
-                helper.insert(&uid, &user_status)?;
-                Ok((
-                    StatusCode::OK,
-                    format!("User @{} demoted to normal\n", user_status.data().login),
-                ))
+            search::reindex(&helper, &topic, &topic_data.description, &post.description)?;
+            topic_data.description = post.description.clone();
+            if let Some(url) = &post.url {
+                topic_data.url = Some(url.clone()).filter(|u| !u.is_empty());
+            }
+            topic_data.version += 1;
+            topic_data.updated_at = now();
+            helper.insert(&topic, &topic_data)?;
+            Ok((TopicRes::new(topic_data, true), None))
+        } else {
+            if state.require_tag && initial_tags.is_empty() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "tag_required",
+                    "At least one tag is required",
+                )
+                    .into());
+            }
+            let is_admin: bool = user_status.is_admin();
+            let user: &mut UserData = user_status.active_data_mut()?;
+            let mut topic_data = Topic {
+                author: uid,
+                description: post.description.clone(),
+                tags: HashMap::new(),
+                pending_tags: HashSet::new(),
+                voters: HashMap::new(),
+                created_at: now(),
+                updated_at: now(),
+                locked: false,
+                allowed_tags: HashSet::new(),
+                denied_tags: HashSet::new(),
+                url: post.url.clone().filter(|u| !u.is_empty()),
+                version: 0,
+                visibility: Visibility::Public,
+            };
+            for tag in initial_tags {
+                let tag: String = canonical_tag(&helper, tag)?;
+                // 与投票一样, 管理员可以绕过
+                if state.is_blocked_tag(&tag) && !is_admin {
+                    return Err((StatusCode::BAD_REQUEST, "tag_blocked", "Tag is blocked").into());
+                }
+                topic_data.seed_tag(tag);
             }
+            helper.insert(&topic, &topic_data)?;
+            search::reindex(&helper, &topic, "", &topic_data.description)?;
+
+            let mut top: Top = helper.get(&"")?.unwrap_or_default();
+            top.0.push(topic.clone());
+            helper.insert(&"", &top)?;
+
+            user.topics.push(topic.clone());
+            helper.insert(&uid, &user_status)?;
+            let created = (!topic_data.is_private()).then(|| TopicCreated {
+                name: topic.clone(),
+                author: uid.to_string(),
+                created_at: topic_data.created_at,
+            });
+            Ok((TopicRes::new(topic_data, true), created))
         }
-    })
+    })?;
+
+    // 提交后再广播; 没有订阅者时 send 失败, 忽略
+    if let Some(created) = created {
+        let _ = state.topic_events.send(created);
+    }
+    Ok(Json(res))
+}
+
+// 全站新建 topic 的 SSE; 跟不上的订阅者丢弃积压, 收到一条 lag 事件
+async fn topic_events(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    use tokio_stream::wrappers::{BroadcastStream, errors::BroadcastStreamRecvError};
+    let stream = BroadcastStream::new(state.topic_events.subscribe()).map(|event| {
+        Ok(match event {
+            Ok(created) => Event::default()
+                .event("topic")
+                .json_data(created)
+                .unwrap_or_default(),
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                Event::default().event("lag").data(skipped.to_string())
+            }
+        })
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn del_topic(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    headers: HeaderMap,
+    Path(topic): Path<String>,
+) -> Result<Response<Body>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx("del_topic", &topic, Some(uid));
+    state.transaction(|helper| {
+        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+        helper
+            .get_or_not_found::<_, UserStatus>(&uid)?
+            .as_verified(uid, topic_data.author)?;
+        check_if_match(&headers, &topic_data)?;
+
+        remove_topic(&helper, &topic, &topic_data)?;
+        Ok(())
+    })?;
+    // 浏览器表单提交时跳回首页, API 客户端直接 204
+    match wants_html(&headers) {
+        true => Ok(redirect_see_other(&state, "/")),
+        false => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+fn wants_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|t| t.trim().starts_with("text/html")))
+}
+
+/// `303 See Other` to `path` under `base_path`; `path` must start with `/`.
+fn redirect_see_other(state: &AppState, path: &str) -> Response<Body> {
+    Redirect::to(&format!("{}{path}", state.base_path)).into_response()
+}
+
+// 删除 topic 记录及其在作者, top 和索引中的引用
+fn remove_topic(helper: &DbHelper<'_>, topic: &String, topic_data: &Topic) -> Result<(), AppError> {
+    if let Some(mut author_status) = helper.get::<_, UserStatus>(&topic_data.author)? {
+        author_status.data_mut().topics.retain(|t| t != topic);
+        helper.insert(&topic_data.author, &author_status)?;
+    }
+
+    let mut top: Top = helper.get(&"")?.unwrap_or_default();
+    top.0.retain(|t| t != topic);
+    helper.insert(&"", &top)?;
+
+    search::reindex(helper, topic, &topic_data.description, "")?;
+    helper.remove::<_, TagHistory>(topic)?;
+    helper.remove::<_, Topic>(topic)?;
+    Ok(())
+}
+
+// tag...
+
+#[derive(Deserialize)]
+struct TagPost {
+    tag: String,
+}
+
+impl Validate for TagPost {
+    fn validate(&self, state: &AppState, errors: &mut Vec<FieldError>) {
+        if let Err(e) = state.tag_limits.validate_tag(&self.tag) {
+            errors.push(e);
+        }
+    }
+}
+
+// 删除和撤票只要求非空, 调整长度限制后旧的 tag 仍然可以删
+#[derive(Deserialize)]
+struct RemoveTagPost {
+    tag: String,
+}
+
+impl Validate for RemoveTagPost {
+    fn validate(&self, _state: &AppState, errors: &mut Vec<FieldError>) {
+        if self.tag.is_empty() {
+            errors.push(FieldError::new("tag", "tag_empty", "Tag is empty"));
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TagChange {
+    tag: String,
+    action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_count: Option<u32>,
+}
+
+// 所有修改 tag 的接口统一返回 { topic, changed }; 批量接口的 changed 是数组
+#[derive(Serialize)]
+struct TagMutationRes<C = TagChange> {
+    topic: TopicRes,
+    changed: C,
+}
+
+impl TagMutationRes {
+    fn new(topic: Topic, show_pending: bool, tag: String, action: &'static str) -> Self {
+        let new_count: Option<u32> = topic.tags.get(&tag).copied();
+        Self {
+            topic: TopicRes::new(topic, show_pending),
+            changed: TagChange {
+                tag,
+                action,
+                new_count,
+            },
+        }
+    }
+}
+
+impl TagMutationRes<Vec<TagChange>> {
+    fn batch(topic: Topic, show_pending: bool, mut changed: Vec<TagChange>) -> Self {
+        changed.sort_by(|a, b| a.tag.cmp(&b.tag));
+        Self {
+            topic: TopicRes::new(topic, show_pending),
+            changed,
+        }
+    }
+}
+
+async fn add_tags_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    ValidatedJson(post): ValidatedJson<TagPost>,
+) -> Result<Json<TagMutationRes>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: Option<u64> = token.lock().await.get_sub();
+    tagme::record_ctx("add_tag", &topic, uid);
+
+    state.transaction(|helper| {
+        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
+        check_unlocked(&helper, &topic_data, uid)?;
+        let user_status: Option<UserStatus> =
+            uid.map(|uid| helper.get(&uid)).transpose()?.flatten();
+        let is_owner: bool = uid
+            .zip(user_status.as_ref())
+            .is_some_and(|(uid, s)| s.verified_data(uid, topic_data.author).is_ok());
+        let tag: String = canonical_tag(&helper, &post.tag)?;
+        // 管理员可以绕过, 用于纠正误封
+        if state.is_blocked_tag(&tag) && !user_status.is_some_and(|s| s.is_admin()) {
+            return Err((StatusCode::BAD_REQUEST, "tag_blocked", "Tag is blocked").into());
+        }
+
+        // 每个登录用户对每个 tag 只算一票, 重复投票不改变计数
+        if let Some(uid) = uid {
+            let first_vote: bool = topic_data
+                .voters
+                .entry(tag.clone())
+                .or_default()
+                .insert(uid);
+            if !first_vote && topic_data.tags.contains_key(&tag) {
+                return Ok(Json(TagMutationRes::new(topic_data, is_owner, tag, "none")));
+            }
+        }
+        // 登录用户记一票, 匿名按 anonymous_vote_weight; 按用户去重只对登录用户有效
+        let weight: u32 = match uid {
+            Some(_) => 1,
+            None => state.anonymous_vote_weight,
+        };
+        // 权重为 0 的匿名投票不改变计数, 也不写入历史和 updated_at
+        if weight == 0 && topic_data.tags.contains_key(&tag) {
+            return Ok(Json(TagMutationRes::new(topic_data, is_owner, tag, "none")));
+        }
+        let action: &'static str = if let Some(count) = topic_data.tags.get_mut(&tag) {
+            *count = count.saturating_add(weight);
+            "vote"
+        } else if is_owner {
+            topic_data.pending_tags.remove(&tag);
+            topic_data.tags.insert(tag.clone(), 1);
+            "add"
+        } else if topic_data.permits_tag(&tag) {
+            topic_data.pending_tags.insert(tag.clone());
+            "suggest"
+        } else {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "tag_not_allowed",
+                "Tag is not allowed on this topic",
+            )
+                .into());
+        };
+
+        topic_data.updated_at = now();
+        helper.insert(&topic, &topic_data)?;
+        TagHistory::append(
+            &helper,
+            &topic,
+            TagEvent {
+                tag: tag.clone(),
+                action: action.to_string(),
+                actor: uid,
+                count_after: topic_data.tags.get(&tag).copied(),
+                timestamp: now(),
+            },
+        )?;
+        Ok(Json(TagMutationRes::new(topic_data, is_owner, tag, action)))
+    })
+}
+
+async fn del_tags_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    headers: HeaderMap,
+    Path(topic): Path<String>,
+    ValidatedJson(post): ValidatedJson<RemoveTagPost>,
+) -> Result<Json<TagMutationRes>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx("del_tag", &topic, Some(uid));
+    state.transaction(|helper| {
+        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
+        helper
+            .get_or_not_found::<_, UserStatus>(&uid)?
+            .verified_data(uid, topic_data.author)?;
+        check_unlocked(&helper, &topic_data, Some(uid))?;
+        check_if_match(&headers, &topic_data)?;
+
+        topic_data.tags.remove(&post.tag);
+        topic_data.pending_tags.remove(&post.tag);
+        topic_data.voters.remove(&post.tag);
+        topic_data.updated_at = now();
+        helper.insert(&topic, &topic_data)?;
+        TagHistory::append(
+            &helper,
+            &topic,
+            TagEvent {
+                tag: post.tag.clone(),
+                action: "delete".to_string(),
+                actor: Some(uid),
+                count_after: None,
+                timestamp: now(),
+            },
+        )?;
+        Ok(Json(TagMutationRes::new(
+            topic_data,
+            true,
+            post.tag.clone(),
+            "delete",
+        )))
+    })
+}
+
+// 撤回自己的一票, 减到 0 时移除 tag; 没投过票 (含只存在于 pending 的 tag) 时不做修改.
+// 匿名投票无法追溯到人, 不能撤回
+async fn unvote_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    ValidatedJson(post): ValidatedJson<RemoveTagPost>,
+) -> Result<Json<TagMutationRes>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx("unvote_tag", &topic, Some(uid));
+
+    state.transaction(|helper| {
+        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
+        check_unlocked(&helper, &topic_data, Some(uid))?;
+        let is_owner: bool = helper
+            .get::<_, UserStatus>(&uid)?
+            .is_some_and(|s| s.verified_data(uid, topic_data.author).is_ok());
+        let tag: String = canonical_tag(&helper, &post.tag)?;
+        let voted: bool = topic_data.tags.contains_key(&tag)
+            && topic_data
+                .voters
+                .get_mut(&tag)
+                .is_some_and(|voters| voters.remove(&uid));
+        if !voted {
+            return Ok(Json(TagMutationRes::new(topic_data, is_owner, tag, "none")));
+        }
+
+        let count: &mut u32 = topic_data.tags.entry(tag.clone()).or_default();
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            topic_data.tags.remove(&tag);
+            topic_data.voters.remove(&tag);
+        }
+        topic_data.updated_at = now();
+        helper.insert(&topic, &topic_data)?;
+        TagHistory::append(
+            &helper,
+            &topic,
+            TagEvent {
+                tag: tag.clone(),
+                action: "unvote".to_string(),
+                actor: Some(uid),
+                count_after: topic_data.tags.get(&tag).copied(),
+                timestamp: now(),
+            },
+        )?;
+        Ok(Json(TagMutationRes::new(
+            topic_data, is_owner, tag, "unvote",
+        )))
+    })
+}
+
+// GET /voted/topic/{*topic}?tag=
+async fn voted_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let tag = query
+        .get("tag")
+        .ok_or((StatusCode::BAD_REQUEST, "No tag"))?;
+    let uid: Option<u64> = token.lock().await.get_sub();
+
+    state.transaction(|helper| {
+        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+        // 与 get_topic 一致: 私有 topic 对其他人不存在
+        let user_status: Option<UserStatus> =
+            uid.map(|uid| helper.get(&uid)).transpose()?.flatten();
+        let is_owner: bool = uid
+            .zip(user_status.as_ref())
+            .is_some_and(|(uid, s)| s.verified_data(uid, topic_data.author).is_ok());
+        if topic_data.is_private() && !is_owner {
+            return Err(AppError::not_found_named(Topic::name(), &topic));
+        }
+        // 投票按同义词归一后的 tag 记录
+        let tag: String = canonical_tag(&helper, tag)?;
+        if !topic_data.tags.contains_key(&tag) {
+            return Err(AppError::not_found_named("Tag", &tag));
+        }
+        let voted: bool = uid.is_some_and(|uid| {
+            topic_data
+                .voters
+                .get(&tag)
+                .is_some_and(|voters| voters.contains(&uid))
+        });
+        Ok(Json(json!({ "voted": voted })))
+    })
+}
+
+// 待审核 tag 分页, 仅作者或管理员可见
+async fn pending_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    page: Pagination,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    // 暂未记录建议时间, 只支持按字母排序
+    if query.get("sort").is_some_and(|s| s != "alpha") {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "unsupported_sort",
+            "Unsupported sort",
+        )
+            .into());
+    }
+    let uid: u64 = token.lock().await.auth()?;
+
+    state.transaction(|helper| {
+        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+        helper
+            .get_or_not_found::<_, UserStatus>(&uid)?
+            .as_verified(uid, topic_data.author)?;
+
+        let mut pending: Vec<&String> = topic_data.pending_tags.iter().collect();
+        pending.sort();
+        Ok(Json(json!({
+            "total": pending.len(),
+            "pending": page.apply(pending),
+        })))
+    })
+}
+
+async fn approve_all_handler(
+    state: State<Arc<AppState>>,
+    token: Extension<Arc<Mutex<OptionalToken>>>,
+    path: Path<String>,
+) -> Result<Json<TagMutationRes<Vec<TagChange>>>, AppError> {
+    resolve_all_pending(state, token, path, true).await
+}
+
+async fn reject_all_handler(
+    state: State<Arc<AppState>>,
+    token: Extension<Arc<Mutex<OptionalToken>>>,
+    path: Path<String>,
+) -> Result<Json<TagMutationRes<Vec<TagChange>>>, AppError> {
+    resolve_all_pending(state, token, path, false).await
+}
+
+// 每次最多处理 MAX_BATCH 个, 剩下的留在 pending 里
+async fn resolve_all_pending(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    approve: bool,
+) -> Result<Json<TagMutationRes<Vec<TagChange>>>, AppError> {
+    const MAX_BATCH: usize = 500;
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx(
+        match approve {
+            true => "approve_all",
+            false => "reject_all",
+        },
+        &topic,
+        Some(uid),
+    );
+
+    state.transaction(|helper| {
+        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
+        let user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        user_status.as_verified(uid, topic_data.author)?;
+        check_unlocked(&helper, &topic_data, Some(uid))?;
+
+        let batch: Vec<String> = topic_data
+            .pending_tags
+            .iter()
+            .take(MAX_BATCH)
+            .cloned()
+            .collect();
+        let mut changed: Vec<TagChange> = Vec::with_capacity(batch.len());
+        for tag in batch {
+            topic_data.pending_tags.remove(&tag);
+            // 被屏蔽或短于 min_tag_len 的 tag 直接丢弃
+            let approved: bool = approve
+                && state.tag_limits.validate_tag(&tag).is_ok()
+                && (user_status.is_admin() || !state.is_blocked_tag(&tag));
+            if approved && !topic_data.tags.contains_key(&tag) {
+                topic_data.seed_tag(tag.clone());
+            }
+            let action: &'static str = match approved {
+                true => "approve",
+                false => "reject",
+            };
+            changed.push(TagChange {
+                tag: tag.clone(),
+                action,
+                new_count: topic_data.tags.get(&tag).copied(),
+            });
+            TagHistory::append(
+                &helper,
+                &topic,
+                TagEvent {
+                    action: action.to_string(),
+                    actor: Some(uid),
+                    count_after: topic_data.tags.get(&tag).copied(),
+                    tag,
+                    timestamp: now(),
+                },
+            )?;
+        }
+        topic_data.updated_at = now();
+        helper.insert(&topic, &topic_data)?;
+        Ok(Json(TagMutationRes::batch(topic_data, true, changed)))
+    })
+}
+
+#[derive(Serialize, Clone, Copy)]
+struct SkippedRow {
+    line: u64,
+    message: &'static str,
+}
+
+#[derive(Serialize)]
+struct ImportRes {
+    #[serde(flatten)]
+    mutation: TagMutationRes<Vec<TagChange>>,
+    imported: usize,
+    skipped: Vec<SkippedRow>,
+}
+
+// text/csv 的 tag,count 行, 与现有票数相加; 格式错误的行跳过并在结果中列出
+async fn import_tags_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    headers: HeaderMap,
+    Path(topic): Path<String>,
+    body: String,
+) -> Result<Json<ImportRes>, AppError> {
+    const MAX_ROWS: usize = 1000;
+    let is_csv: bool = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/csv"));
+    if !is_csv {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            "Expected text/csv",
+        )
+            .into());
+    }
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx("import_tags", &topic, Some(uid));
+
+    let mut rows: Vec<(u64, String, u32)> = Vec::new();
+    let mut skipped: Vec<SkippedRow> = Vec::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+    for record in reader.records() {
+        let line: u64 = match &record {
+            Ok(r) => r.position().map_or(0, |p| p.line()),
+            Err(e) => e.position().map_or(0, |p| p.line()),
+        };
+        let Ok(record) = record else {
+            skipped.push(SkippedRow {
+                line,
+                message: "Malformed row",
+            });
+            continue;
+        };
+        // 允许可选的表头
+        if line == 1 && record.iter().eq(["tag", "count"]) {
+            continue;
+        }
+        if rows.len() + skipped.len() >= MAX_ROWS {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "too_many_rows",
+                "Too many rows",
+            )
+                .into());
+        }
+        let (Some(tag), Some(count), None) = (record.get(0), record.get(1), record.get(2)) else {
+            skipped.push(SkippedRow {
+                line,
+                message: "Malformed row",
+            });
+            continue;
+        };
+        if let Err(e) = state.tag_limits.validate_tag(tag) {
+            skipped.push(SkippedRow {
+                line,
+                message: e.message,
+            });
+            continue;
+        }
+        match count.parse::<u32>() {
+            Ok(count) if count > 0 => rows.push((line, tag.to_string(), count)),
+            _ => skipped.push(SkippedRow {
+                line,
+                message: "Invalid count",
+            }),
+        }
+    }
+
+    state.transaction(|helper| {
+        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
+        let user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        user_status.as_verified(uid, topic_data.author)?;
+        check_unlocked(&helper, &topic_data, Some(uid))?;
+
+        let mut skipped: Vec<SkippedRow> = skipped.clone();
+        let mut imported: usize = 0;
+        // 同一 tag 出现多行时只报告最终票数
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for (line, tag, count) in &rows {
+            let tag: String = canonical_tag(&helper, tag)?;
+            if state.is_blocked_tag(&tag) && !user_status.is_admin() {
+                skipped.push(SkippedRow {
+                    line: *line,
+                    message: "Tag is blocked",
+                });
+                continue;
+            }
+            topic_data.pending_tags.remove(&tag);
+            // 新 tag 的票数算在作者名下, 之后作者再投不重复计数
+            if !topic_data.tags.contains_key(&tag) {
+                let author: u64 = topic_data.author;
+                topic_data
+                    .voters
+                    .entry(tag.clone())
+                    .or_default()
+                    .insert(author);
+            }
+            let total: &mut u32 = topic_data.tags.entry(tag.clone()).or_insert(0);
+            *total = total.saturating_add(*count);
+            counts.insert(tag.clone(), *total);
+            TagHistory::append(
+                &helper,
+                &topic,
+                TagEvent {
+                    count_after: Some(*total),
+                    tag,
+                    action: "import".to_string(),
+                    actor: Some(uid),
+                    timestamp: now(),
+                },
+            )?;
+            imported += 1;
+        }
+        topic_data.updated_at = now();
+        helper.insert(&topic, &topic_data)?;
+        skipped.sort_by_key(|row| row.line);
+        let changed: Vec<TagChange> = counts
+            .into_iter()
+            .map(|(tag, count)| TagChange {
+                tag,
+                action: "import",
+                new_count: Some(count),
+            })
+            .collect();
+        Ok(Json(ImportRes {
+            mutation: TagMutationRes::batch(topic_data, true, changed),
+            imported,
+            skipped,
+        }))
+    })
+}
+
+#[derive(Deserialize)]
+struct TagPolicyPost {
+    allowed_tags: Option<Vec<String>>, // 省略则保持不变
+    denied_tags: Option<Vec<String>>,
+}
+
+async fn tag_policy_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    AppJson(post): AppJson<TagPolicyPost>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let all_tags = post.allowed_tags.iter().chain(&post.denied_tags).flatten();
+    for tag in all_tags {
+        state.tag_limits.validate_tag(tag)?;
+    }
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx("tag_policy", &topic, Some(uid));
+
+    state.transaction(|helper| {
+        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
+        helper
+            .get_or_not_found::<_, UserStatus>(&uid)?
+            .as_verified(uid, topic_data.author)?;
+
+        let canonical = |tags: &Vec<String>| -> Result<HashSet<String>, AppError> {
+            tags.iter().map(|tag| canonical_tag(&helper, tag)).collect()
+        };
+        if let Some(tags) = &post.allowed_tags {
+            topic_data.allowed_tags = canonical(tags)?;
+        }
+        if let Some(tags) = &post.denied_tags {
+            topic_data.denied_tags = canonical(tags)?;
+        }
+        helper.insert(&topic, &topic_data)?;
+        Ok(Json(json!({
+            "allowed_tags": topic_data.allowed_tags,
+            "denied_tags": topic_data.denied_tags,
+        })))
+    })
+}
+
+#[derive(Deserialize)]
+struct MoveTagPost {
+    tag: String,
+    to_topic: String,
+}
+
+// 把一个 tag 连同票数和投票者移到另一个 topic, 已有则相加
+async fn move_tag_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    AppJson(post): AppJson<MoveTagPost>,
+) -> Result<Json<TagMutationRes>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let to_topic: String = state.normalize_topic(&post.to_topic)?;
+    if topic == to_topic {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Cannot move a tag onto the same topic",
+        )
+            .into());
+    }
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx("move_tag", &topic, Some(uid));
+
+    state.transaction(|helper| {
+        let mut from_data: Topic = helper.get_or_not_found(&topic)?;
+        let mut to_data: Topic = helper.get_or_not_found(&to_topic)?;
+        let user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        user_status.as_verified(uid, from_data.author)?;
+        user_status.as_verified(uid, to_data.author)?;
+        check_unlocked(&helper, &from_data, Some(uid))?;
+        check_unlocked(&helper, &to_data, Some(uid))?;
+
+        let tag: String = canonical_tag(&helper, &post.tag)?;
+        let count: u32 = from_data
+            .tags
+            .remove(&tag)
+            .ok_or_else(|| AppError::not_found_named("Tag", &tag))?;
+        let total: &mut u32 = to_data.tags.entry(tag.clone()).or_insert(0);
+        *total = total.saturating_add(count);
+        to_data.pending_tags.remove(&tag);
+        if let Some(voters) = from_data.voters.remove(&tag) {
+            to_data
+                .voters
+                .entry(tag.clone())
+                .or_default()
+                .extend(voters);
+        }
+        from_data.updated_at = now();
+        to_data.updated_at = now();
+        helper.insert(&topic, &from_data)?;
+        helper.insert(&to_topic, &to_data)?;
+
+        for (name, data) in [(&topic, &from_data), (&to_topic, &to_data)] {
+            TagHistory::append(
+                &helper,
+                name,
+                TagEvent {
+                    tag: tag.clone(),
+                    action: "move".to_string(),
+                    actor: Some(uid),
+                    count_after: data.tags.get(&tag).copied(),
+                    timestamp: now(),
+                },
+            )?;
+        }
+        Ok(Json(TagMutationRes::new(to_data, true, tag, "move")))
+    })
+}
+
+// tag 变更记录, 新的在前, 仅作者或管理员可见
+async fn tag_history_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    page: Pagination,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: u64 = token.lock().await.auth()?;
+
+    state.transaction(|helper| {
+        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+        helper
+            .get_or_not_found::<_, UserStatus>(&uid)?
+            .as_verified(uid, topic_data.author)?;
+
+        let history: TagHistory = helper.get(&topic)?.unwrap_or_default();
+        let events: Vec<serde_json::Value> = page
+            .apply(history.0.iter().rev())
+            .into_iter()
+            .map(|e| {
+                json!({
+                    "tag": e.tag,
+                    "action": e.action,
+                    "actor": e.actor.map(|uid| uid.to_string()),
+                    "count_after": e.count_after,
+                    "timestamp": e.timestamp,
+                })
+            })
+            .collect();
+        Ok(Json(json!({
+            "total": history.0.len(),
+            "events": events,
+        })))
+    })
+}
+
+#[derive(Serialize)]
+struct VoteBucket {
+    bucket_start: i64,
+    votes: u64,
+}
+
+// ?tag=&bucket=hour|day|week&since=&until=, 只统计 tag 历史里的 vote 事件, 只返回非空的桶
+// 历史只保留最近 200 条, 更早的投票不在统计内
+async fn tag_timeseries_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<VoteBucket>>, AppError> {
+    const WEEK_OFFSET: i64 = 4 * 86400; // 1970-01-01 是周四, 让周桶从周一开始
+    let topic: String = state.normalize_topic(&topic)?;
+    let tag: &String =
+        query
+            .get("tag")
+            .ok_or(FieldError::new("tag", "tag_empty", "Tag is empty"))?;
+    let (width, offset): (i64, i64) = match query.get("bucket").map_or("day", String::as_str) {
+        "hour" => (3600, 0),
+        "day" => (86400, 0),
+        "week" => (7 * 86400, WEEK_OFFSET),
+        _ => {
+            return Err(
+                FieldError::new("bucket", "unsupported_bucket", "Unsupported bucket").into(),
+            );
+        }
+    };
+    let bound = |key: &str, end_of_day: bool| -> Result<Option<i64>, AppError> {
+        query
+            .get(key)
+            .map(|s| {
+                parse_time(s, end_of_day)
+                    .ok_or(FieldError::new(key, "invalid_time", "Invalid time").into())
+            })
+            .transpose()
+    };
+    let since: i64 = bound("since", false)?.unwrap_or(i64::MIN);
+    let until: i64 = bound("until", true)?.unwrap_or(i64::MAX);
+    let uid: u64 = token.lock().await.auth()?;
+
+    state.transaction(|helper| {
+        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+        helper
+            .get_or_not_found::<_, UserStatus>(&uid)?
+            .as_verified(uid, topic_data.author)?;
+        let tag: String = canonical_tag(&helper, tag)?;
+
+        let history: TagHistory = helper.get(&topic)?.unwrap_or_default();
+        let mut buckets: std::collections::BTreeMap<i64, u64> = Default::default();
+        history
+            .0
+            .iter()
+            .filter(|e| e.action == "vote" && e.tag == tag)
+            .filter(|e| (since..=until).contains(&e.timestamp))
+            .for_each(|e| {
+                let start = (e.timestamp - offset).div_euclid(width) * width + offset;
+                *buckets.entry(start).or_default() += 1;
+            });
+        Ok(Json(
+            buckets
+                .into_iter()
+                .map(|(bucket_start, votes)| VoteBucket {
+                    bucket_start,
+                    votes,
+                })
+                .collect(),
+        ))
+    })
+}
+
+// 作者已不存在的 topic 可以被任意登录用户认领; 私有和锁定的只有管理员能认领
+async fn claim_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+) -> Result<Json<TopicRes>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx("claim", &topic, Some(uid));
+
+    state.transaction(|helper| {
+        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
+        let mut user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        // 私有 topic 对其他人不存在
+        if topic_data.is_private() && !user_status.is_admin() {
+            return Err(AppError::not_found_named(Topic::name(), &topic));
+        }
+        if helper.get::<_, UserStatus>(&topic_data.author)?.is_some() {
+            return Err((
+                StatusCode::CONFLICT,
+                "topic_has_author",
+                "Topic still has an author",
+            )
+                .into());
+        }
+        if topic_data.locked && !user_status.is_admin() {
+            return Err((StatusCode::FORBIDDEN, "topic_locked", "Topic is locked").into());
+        }
+        user_status.active_data_mut()?.topics.push(topic.clone());
+        helper.insert(&uid, &user_status)?;
+
+        topic_data.author = uid;
+        topic_data.updated_at = now();
+        helper.insert(&topic, &topic_data)?;
+        Ok(Json(TopicRes::new(topic_data, true)))
+    })
+}
+
+async fn lock_handler(
+    state: State<Arc<AppState>>,
+    token: Extension<Arc<Mutex<OptionalToken>>>,
+    path: Path<String>,
+) -> Result<Json<TopicRes>, AppError> {
+    set_locked(state, token, path, true).await
+}
+
+async fn unlock_handler(
+    state: State<Arc<AppState>>,
+    token: Extension<Arc<Mutex<OptionalToken>>>,
+    path: Path<String>,
+) -> Result<Json<TopicRes>, AppError> {
+    set_locked(state, token, path, false).await
+}
+
+// 描述仍可由作者修改, 只冻结 tag
+async fn set_locked(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(topic): Path<String>,
+    locked: bool,
+) -> Result<Json<TopicRes>, AppError> {
+    let topic: String = state.normalize_topic(&topic)?;
+    let uid: u64 = token.lock().await.auth()?;
+    tagme::record_ctx(
+        match locked {
+            true => "lock",
+            false => "unlock",
+        },
+        &topic,
+        Some(uid),
+    );
+
+    state.transaction(|helper| {
+        let mut topic_data: Topic = helper.get_or_not_found(&topic)?;
+        helper
+            .get_or_not_found::<_, UserStatus>(&uid)?
+            .as_verified(uid, topic_data.author)?;
+
+        topic_data.locked = locked;
+        helper.insert(&topic, &topic_data)?;
+        Ok(Json(TopicRes::new(topic_data, true)))
+    })
+}
+
+// oauth
+
+async fn oauth_login(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let oauth_state = OAuthState::issue(&state.oauth_redirect_uri);
+    let encoded: String = oauth_state.encode();
+    let mut params: Vec<(&str, &str)> =
+        vec![("client_id", &state.oauth_client_id), ("state", &encoded)];
+    if !state.oauth_redirect_uri.is_empty() {
+        params.push(("redirect_uri", &state.oauth_redirect_uri));
+    }
+    let url = reqwest::Url::parse_with_params("https://github.com/login/oauth/authorize", params)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Invalid authorize URL"))?;
+    let cookie: HeaderValue = oauth_state
+        .to_cookie(&state)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue state"))?;
+    Ok((
+        StatusCode::FOUND,
+        [(header::LOCATION, url.to_string())],
+        [(header::SET_COOKIE, cookie)],
+    ))
+}
+
+#[derive(Deserialize)]
+struct EmbedTokenPost {
+    topic: String,
+    #[serde(default = "default_embed_ttl")]
+    ttl_secs: i64,
+}
+
+fn default_embed_ttl() -> i64 {
+    86400
+}
+
+// 用未过期且未吊销的 token 换一个新的, exp 重新计算; 已过期或已吊销的在中间件里就被丢弃了
+async fn refresh_token_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+) -> Result<Response<Body>, AppError> {
+    let mut token = token.lock().await;
+    // API key 的服务身份不能换出 token
+    let uid: u64 = token.as_ref().map(|t| t.sub).ok_or((
+        StatusCode::UNAUTHORIZED,
+        "login_required",
+        "Login required",
+    ))?;
+    state.transaction(|helper| helper.get_or_not_found::<_, UserStatus>(&uid)?.as_active())?;
+
+    let fresh = Token::issue(&state, uid)?;
+    token.replace(fresh.clone());
+    // cookie 模式下不能把 token 放进响应头暴露给脚本, 交给中间件写 cookie
+    match token.is_from_cookie() {
+        true => Ok(StatusCode::OK.into_response()),
+        false => Ok(fresh.into_response()),
+    }
+}
+
+// 作者为自己的 topic 签发只读的嵌入 token, 最长 30 天
+async fn embed_token_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    AppJson(post): AppJson<EmbedTokenPost>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let topic: String = state.normalize_topic(&post.topic)?;
+    if !(1..=30 * 86400).contains(&post.ttl_secs) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid ttl_secs").into());
+    }
+    let uid: u64 = token.lock().await.auth()?;
+
+    state.transaction(|helper| {
+        let topic_data: Topic = helper.get_or_not_found(&topic)?;
+        helper
+            .get_or_not_found::<_, UserStatus>(&uid)?
+            .as_verified(uid, topic_data.author)?;
+        let embed = EmbedToken::new(topic.clone(), post.ttl_secs);
+        Ok(Json(json!({
+            "token": embed.encode(),
+            "topic": topic,
+            "expires_in": post.ttl_secs,
+        })))
+    })
+}
+
+#[derive(Deserialize)]
+struct OAuthCallbackQuery {
+    code: Option<String>,
+    state: Option<String>, // /oauth/login 签发的 OAuthState
+    error: Option<String>, // 用户在 GitHub 上拒绝授权
+}
+
+// state 校验失败时不向 GitHub 换取 token; 用过的 state cookie 随响应清除
+async fn oauth_callback(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<OAuthCallbackQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let oauth_state: OAuthState = query
+        .state
+        .as_deref()
+        .and_then(OAuthState::decode)
+        .filter(|s| s.is_valid_for(&headers))
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "oauth_state_invalid",
+            "Invalid OAuth state",
+        ))?;
+    // 授权时用的 redirect_uri 必须与当前配置完全一致, 不一致时不去换取 token
+    if oauth_state.redirect_uri != state.oauth_redirect_uri {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "redirect_uri_mismatch",
+            "Redirect URI mismatch",
+        )
+            .into());
+    }
+    let mut response: Response<Body> = oauth_exchange(&state, &query)
+        .await
+        .unwrap_or_else(IntoResponse::into_response);
+    response
+        .headers_mut()
+        .append(header::SET_COOKIE, OAuthState::clear_cookie(&state));
+    Ok(response)
+}
+
+async fn oauth_exchange(
+    state: &AppState,
+    query: &OAuthCallbackQuery,
+) -> Result<Response<Body>, AppError> {
+    if query.error.is_some() {
+        return Err((StatusCode::BAD_REQUEST, "Authorization denied").into());
+    }
+    let code: &String = query
+        .code
+        .as_ref()
+        .ok_or((StatusCode::BAD_REQUEST, "No code"))?;
+    let client: &reqwest::Client = &state.http;
+    let mut token_request = json!({
+        "client_id": state.oauth_client_id,
+        "client_secret": state.oauth_client_secrets,
+        "code": code,
+    });
+    // GitHub 要求与授权时的 redirect_uri 完全一致, 否则拒绝换取 token
+    if !state.oauth_redirect_uri.is_empty() {
+        token_request["redirect_uri"] = json!(state.oauth_redirect_uri);
+    }
+
+    let resp = client
+        .post("https://github.com/login/oauth/access_token")
+        .header("Accept", "application/json")
+        .json(&token_request)
+        .send()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to request token"))?;
+    let token_data: serde_json::Value = resp
+        .json()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse token"))?;
+    if token_data["error"] == "redirect_uri_mismatch" {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "redirect_uri_mismatch",
+            "Redirect URI mismatch",
+        )
+            .into());
+    }
+    let access_token: String = token_data["access_token"]
+        .as_str()
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid token response"))?
+        .to_string();
+
+    let user_resp = client
+        .get("https://api.github.com/user")
+        .bearer_auth(&access_token)
+        .header("User-Agent", "KemiAmu:tagme")
+        .send()
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to request user info",
+            )
+        })?;
+    let user_data: serde_json::Value = user_resp.json().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to parse user info",
+        )
+    })?;
+    let github_id = user_data["id"]
+        .as_u64()
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid user id"))?;
+    let login = user_data["login"].as_str().unwrap_or("").to_string();
+    let name = user_data["name"].as_str().unwrap_or("").to_string();
+    let avatar_url = user_data["avatar_url"].as_str().unwrap_or("").to_string();
+    let bio = user_data["bio"].as_str().unwrap_or("").to_string();
+
+    if !state.login_policy.permits(github_id, &login) {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            Html("<!doctype html><html><body>Access not permitted.</body></html>"),
+        )
+            .into_response());
+    }
+    let token = Token::issue(state, github_id)?;
+
+    state.transaction(|helper| {
+        let mut user_status: UserStatus = helper.get(&github_id)?.unwrap_or_default();
+        let user: &mut UserData = user_status.data_mut();
+        user.access_token = access_token.clone();
+        user.login = login.clone();
+        user.name = name.clone();
+        user.avatar_url = avatar_url.clone();
+        user.bio = bio.clone();
+        helper.insert(&github_id, &user_status)?;
+
+        // cookie 模式下 token 不经过页面脚本
+        if state.cookie_auth {
+            let cookie = token
+                .to_cookie(state)
+                .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue token"))?;
+            return Ok((
+                [(header::SET_COOKIE, cookie)],
+                Redirect::to(&format!("{}/profile", state.base_path)),
+            )
+                .into_response());
+        }
+        Ok(Html(format!(
+            r#"<!doctype html>
+            <html><head><script>
+            localStorage.setItem("Token", "{}");
+            window.location.href = "{}/profile";
+            </script></head></html>"#,
+            token, state.base_path
+        ))
+        .into_response())
+    })
+}
+
+// 用户在 GitHub 上撤销授权后清除保存的 access_token, 并吊销已签发的 token
+async fn github_webhook(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<StatusCode, AppError> {
+    let signature: &str = headers
+        .get("X-Hub-Signature-256")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if state.github_webhook_secret.is_empty()
+        || !tagme::webhook::verify_signature(&state.github_webhook_secret, &body, signature)
+    {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid signature").into());
+    }
+    let event: &str = headers
+        .get("X-GitHub-Event")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let payload: serde_json::Value =
+        serde_json::from_slice(&body).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid payload"))?;
+    if event != "github_app_authorization" || payload["action"] != "revoked" {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    let github_id: u64 = payload["sender"]["id"]
+        .as_u64()
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid user id"))?;
+
+    state.transaction(|helper| {
+        if let Some(mut user_status) = helper.get::<_, UserStatus>(&github_id)? {
+            user_status.data_mut().access_token.clear();
+            helper.insert(&github_id, &user_status)?;
+            helper.insert(&github_id, &Revoked(now()))?;
+            info!("cleared access token of {github_id} after authorization was revoked");
+        }
+        Ok(StatusCode::NO_CONTENT)
+    })
+}
+
+// admin
+
+#[derive(Deserialize)]
+struct AdminQuery {
+    op: Option<u64>,
+    deop: Option<u64>,
+}
+
+// POST /private/admin?op=<uid>; 与 /roles 一样不能降自己, 这样至少还剩一个管理员
+async fn admin_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Query(query): Query<AdminQuery>,
+) -> Result<(StatusCode, String), AppError> {
+    // op 与 deop 必须恰好给出一个
+    let (uid, promote): (u64, bool) = match (query.op, query.deop) {
+        (Some(uid), None) => (uid, true),
+        (None, Some(uid)) => (uid, false),
+        _ => return Err((StatusCode::BAD_REQUEST, "Invalid op/deop").into()),
+    };
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    if !promote && uid == admin_uid {
+        return Err((StatusCode::BAD_REQUEST, "Cannot demote yourself").into());
+    }
+
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        let mut user_status: UserStatus = helper.get_or_not_found(&uid)?;
+        match promote {
+            true => {
+                user_status = UserStatus::Admin(user_status.into_data());
+                helper.insert(&uid, &user_status)?;
+                Ok((
+                    StatusCode::OK,
+                    format!("User @{} promoted to admin\n", user_status.data().login),
+                ))
+            }
+            false => {
+                user_status = UserStatus::Normal(user_status.into_data());
+                helper.insert(&uid, &user_status)?;
+                Ok((
+                    StatusCode::OK,
+                    format!("User @{} demoted to normal\n", user_status.data().login),
+                ))
+            }
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct RolesPost {
+    #[serde(default)]
+    promote: Vec<u64>,
+    #[serde(default)]
+    demote: Vec<u64>,
+}
+
+// 批量升降管理员; 不能降自己, 这样至少还剩一个管理员
+async fn roles_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    AppJson(post): AppJson<RolesPost>,
+) -> Result<Json<HashMap<String, &'static str>>, AppError> {
+    if post.promote.len() + post.demote.len() > 100 {
+        return Err((StatusCode::BAD_REQUEST, "Too many users").into());
+    }
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    if post.demote.contains(&admin_uid) {
+        return Err((StatusCode::BAD_REQUEST, "Cannot demote yourself").into());
+    }
+
+    let results = state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        let mut results: HashMap<String, &'static str> = HashMap::new();
+        let changes = post.promote.iter().map(|uid| (uid, true));
+        for (uid, promote) in changes.chain(post.demote.iter().map(|uid| (uid, false))) {
+            let Some(user_status) = helper.get::<_, UserStatus>(uid)? else {
+                results.insert(uid.to_string(), "not_found");
+                continue;
+            };
+            let result: &'static str = match (promote, user_status.is_admin()) {
+                (true, true) | (false, false) => "unchanged",
+                (true, false) => {
+                    helper.insert(uid, &UserStatus::Admin(user_status.into_data()))?;
+                    "promoted"
+                }
+                (false, true) => {
+                    helper.insert(uid, &UserStatus::Normal(user_status.into_data()))?;
+                    "demoted"
+                }
+            };
+            results.insert(uid.to_string(), result);
+        }
+        Ok(results)
+    })?;
+
+    for (uid, result) in &results {
+        info!("admin {admin_uid} roles change: {uid} {result}");
+    }
+    Ok(Json(results))
+}
+
+async fn config_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()
+    })?;
+    Ok(Json(state.effective_config.clone()))
+}
+
+async fn rotate_key_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+) -> Result<StatusCode, AppError> {
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()
+    })?;
+    if tagme::token::is_key_from_config() {
+        return Err((StatusCode::CONFLICT, "Token secret is set in config").into());
+    }
+
+    tagme::token::rotate_secret_key(TOKEN_KEY_FILE).map_err(|e| {
+        error!("failed to rotate token key: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Failed to rotate key")
+    })?;
+    // 旧 token 已失效, 不要再回写刷新后的旧 token
+    *token.lock().await = OptionalToken::default();
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct SynonymPost {
+    from: String,
+    to: String,
+}
+
+async fn tag_synonym_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    AppJson(post): AppJson<SynonymPost>,
+) -> Result<StatusCode, AppError> {
+    state.tag_limits.validate_tag(&post.from)?;
+    if !post.to.is_empty() {
+        state.tag_limits.validate_tag(&post.to)?;
+    }
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+
+        // 空 to 表示删除
+        if post.to.is_empty() {
+            helper.remove::<_, TagSynonym>(&post.from)?;
+            return Ok(StatusCode::OK);
+        }
+        let mut next: Option<String> = Some(post.to.clone());
+        while let Some(tag) = next {
+            if tag == post.from {
+                return Err((StatusCode::BAD_REQUEST, "synonym_cycle", "Synonym cycle").into());
+            }
+            next = helper.get::<_, TagSynonym>(&tag)?.map(|s| s.0);
+        }
+        helper.insert(&post.from, &TagSynonym(post.to.clone()))?;
+        Ok(StatusCode::OK)
+    })
+}
+
+async fn motd_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    AppJson(post): AppJson<Motd>,
+) -> Result<StatusCode, AppError> {
+    if post.message.chars().count() > 512 {
+        return Err((StatusCode::BAD_REQUEST, "Message is too long").into());
+    }
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        // 空消息表示清除
+        match post.message.is_empty() {
+            true => helper.remove::<_, Motd>(&"")?,
+            false => helper.insert(&"", &post)?,
+        }
+        Ok(StatusCode::OK)
+    })
+}
+
+async fn feature_author_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(uid): Path<u64>,
+) -> Result<StatusCode, AppError> {
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        helper.get_or_not_found::<_, UserStatus>(&uid)?;
+        let mut featured: FeaturedAuthors = helper.get(&"")?.unwrap_or_default();
+        if !featured.0.contains(&uid) {
+            featured.0.push(uid);
+            helper.insert(&"", &featured)?;
+        }
+        Ok(StatusCode::OK)
+    })
+}
+
+async fn unfeature_author_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Path(uid): Path<u64>,
+) -> Result<StatusCode, AppError> {
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        let mut featured: FeaturedAuthors = helper.get(&"")?.unwrap_or_default();
+        featured.0.retain(|&featured_uid| featured_uid != uid);
+        helper.insert(&"", &featured)?;
+        Ok(StatusCode::OK)
+    })
+}
+
+// 分批扫描, 删除无 tag 且长期无活动的 topic; 私有 topic 不在 top 中, 所以扫描全部
+async fn prune_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let min_age_days: u32 = query
+        .get("min_age_days")
+        .map_or(Ok(30), |s| s.parse())
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid min_age_days"))?;
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        Ok(())
+    })?;
+
+    let cutoff: i64 = (min_age_days as i64)
+        .checked_mul(86400)
+        .and_then(|age| now().checked_sub(age))
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid min_age_days"))?;
+    // 旧数据没有 updated_at, 退回 created_at; 两者都没有时无法判断, 不删
+    let is_stale = |topic: &Topic| {
+        let last_active = match topic.updated_at {
+            0 => topic.created_at,
+            t => t,
+        };
+        topic.tags.is_empty()
+            && topic.pending_tags.is_empty()
+            && last_active != 0
+            && last_active < cutoff
+    };
+    let names: Vec<String> = state
+        .scan_all::<Topic>()?
+        .into_iter()
+        .filter(|(_, topic)| is_stale(topic))
+        .filter_map(|(key, _)| String::from_utf8(key).ok())
+        .collect();
+
+    let mut pruned: usize = 0;
+    for chunk in names.chunks(64) {
+        pruned += state.transaction(|helper| {
+            let mut count: usize = 0;
+            for name in chunk {
+                // 扫描之后可能又有了 tag, 重新检查
+                let Some(topic_data) = helper.get::<_, Topic>(name)? else {
+                    continue;
+                };
+                if is_stale(&topic_data) {
+                    remove_topic(&helper, name, &topic_data)?;
+                    count += 1;
+                }
+            }
+            Ok(count)
+        })?;
+    }
+    Ok(Json(json!({ "pruned": pruned })))
+}
+
+// GET /private/admin/raw?prefix=@&key=123, 排查序列化问题用
+async fn raw_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+    let prefix: &str = query.get("prefix").map_or("", String::as_str);
+    let raw_key: &str = query.get("key").map_or("", String::as_str);
+    let key: Vec<u8> = match prefix {
+        p if p == UserStatus::prefix() => raw_key
+            .parse::<u64>()
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid uid"))?
+            .to_le_bytes()
+            .to_vec(),
+        p if [
+            Topic::prefix(),
+            Top::prefix(),
+            search::Posting::prefix(),
+            TagSynonym::prefix(),
+            TagHistory::prefix(),
+            Motd::prefix(),
+        ]
+        .contains(&p) =>
+        {
+            raw_key.as_bytes().to_vec()
+        }
+        _ => return Err((StatusCode::BAD_REQUEST, "Unknown prefix").into()),
+    };
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        let Some(mut bytes) = helper.get_raw(prefix, &key)? else {
+            return Err(AppError::not_found_named("Record", raw_key));
+        };
+
+        // 用户记录里有 GitHub access_token, topic 里有投票者的 uid, 先抹掉再输出
+        let redacted: bool = [UserStatus::prefix(), Topic::prefix()].contains(&prefix);
+        if prefix == UserStatus::prefix() {
+            let mut user_status: UserStatus = rmp_serde::from_slice(&bytes)
+                .map_err(|_| (StatusCode::UNPROCESSABLE_ENTITY, "Cannot redact record"))?;
+            user_status.data_mut().access_token.clear();
+            bytes = rmp_serde::to_vec(&user_status)
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Serialize failed"))?;
+        } else if prefix == Topic::prefix() {
+            let mut topic_data: Topic = rmp_serde::from_slice(&bytes)
+                .map_err(|_| (StatusCode::UNPROCESSABLE_ENTITY, "Cannot redact record"))?;
+            topic_data.voters.clear();
+            bytes = rmp_serde::to_vec(&topic_data)
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Serialize failed"))?;
+        }
+        Ok(Json(json!({
+            "len": bytes.len(),
+            "base64": STANDARD.encode(&bytes),
+            "decoded": rmp_serde::from_slice::<serde_json::Value>(&bytes).ok(),
+            "redacted": redacted,
+        })))
+    })
+}
+
+#[derive(Deserialize)]
+struct MergeUsersPost {
+    from: u64,
+    to: u64,
+    #[serde(default)]
+    transfer_admin: bool,
+}
+
+// 把 from 的 topic, 投票和推荐全部转给 to, 然后删除 from
+async fn merge_users_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    AppJson(post): AppJson<MergeUsersPost>,
+) -> Result<Json<UserInfo>, AppError> {
+    if post.from == post.to {
+        return Err((StatusCode::BAD_REQUEST, "Cannot merge a user into itself").into());
+    }
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+
+    // 他人 topic 上的投票只能扫描得到, 事务内会重新读取
+    let voted: Vec<String> = state
+        .scan_all::<Topic>()?
+        .into_iter()
+        .filter(|(_, topic)| topic.voters.values().any(|v| v.contains(&post.from)))
+        .filter_map(|(key, _)| String::from_utf8(key).ok())
+        .collect();
+
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        let from_status: UserStatus = helper.get_or_not_found(&post.from)?;
+        let mut to_status: UserStatus = helper.get_or_not_found(&post.to)?;
+
+        let own: &[String] = &from_status.data().topics;
+        for name in own.iter().chain(voted.iter().filter(|n| !own.contains(n))) {
+            let Some(mut topic_data) = helper.get::<_, Topic>(name)? else {
+                continue;
+            };
+            if own.contains(name) {
+                topic_data.author = post.to;
+                if !to_status.data().topics.contains(name) {
+                    to_status.data_mut().topics.push(name.clone());
+                }
+            }
+            for (tag, voters) in topic_data.voters.iter_mut() {
+                // 两个账号都投过的 tag 合并后只算一票
+                if voters.remove(&post.from)
+                    && !voters.insert(post.to)
+                    && let Some(count) = topic_data.tags.get_mut(tag)
+                {
+                    *count = count.saturating_sub(1).max(1);
+                }
+            }
+            helper.insert(name, &topic_data)?;
+        }
+
+        let mut featured: FeaturedAuthors = helper.get(&"")?.unwrap_or_default();
+        // 保持推荐顺序, to 已在列表中时只去掉 from
+        if let Some(i) = featured.0.iter().position(|&uid| uid == post.from) {
+            match featured.0.contains(&post.to) {
+                true => drop(featured.0.remove(i)),
+                false => featured.0[i] = post.to,
+            }
+            helper.insert(&"", &featured)?;
+        }
+
+        if post.transfer_admin && from_status.is_admin() && !to_status.is_banned() {
+            to_status = UserStatus::Admin(to_status.into_data());
+        }
+
+        helper.insert(&post.to, &to_status)?;
+        helper.remove::<_, UserStatus>(&post.from)?;
+        Ok(Json(to_status.into_info(post.to)))
+    })
+}
+
+// 边写边算 sha256, 导出不必整个放进内存
+struct HashingWriter<W> {
+    inner: W,
+    hasher: sha2::Sha256,
+    len: usize,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        let written: usize = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_msgpack<T: Serialize>(writer: &mut impl Write, value: &T) -> std::io::Result<()> {
+    rmp_serde::encode::write(writer, value).map_err(std::io::Error::other)
+}
+
+// 只接受 snapshot_dir 下的单个文件名, 不允许带路径或以 . 开头
+fn is_snapshot_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\', '\0'])
+}
+
+// sled 导出写成一串 msgpack 值: 每个集合先是 (类型, 名称), 再逐条写 Some(记录), 以 None 结束;
+// 目前没有导入入口; 已存在的文件不覆盖
+async fn snapshot_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let dir: &std::path::Path = state
+        .snapshot_dir
+        .as_deref()
+        .ok_or((StatusCode::NOT_FOUND, "Snapshots are disabled"))?;
+    let name: &String = query
+        .get("name")
+        .ok_or((StatusCode::BAD_REQUEST, "No name"))?;
+    if !is_snapshot_name(name) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid snapshot name").into());
+    }
+    let path: std::path::PathBuf = dir.join(name);
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()
+    })?;
+
+    let db: sled::Db = state.db().clone();
+    let result = tokio::task::spawn_blocking(move || -> std::io::Result<_> {
+        use sha2::{Digest, Sha256};
+        let file = std::fs::File::create_new(&path)?;
+        let write = || -> std::io::Result<_> {
+            let mut writer = HashingWriter {
+                inner: std::io::BufWriter::new(&file),
+                hasher: Sha256::new(),
+                len: 0,
+            };
+            for (kind, name, items) in db.export() {
+                write_msgpack(&mut writer, &(kind, name))?;
+                for item in items {
+                    write_msgpack(&mut writer, &Some(item))?;
+                }
+                write_msgpack(&mut writer, &None::<Vec<Vec<u8>>>)?;
+            }
+            writer.flush()?;
+            let digest: String = writer
+                .hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            Ok((writer.len, digest))
+        };
+        match write() {
+            Ok((len, digest)) => Ok((path, len, digest)),
+            // 写了一半的文件没用, 删掉以免被误当作快照
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                Err(e)
+            }
+        }
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Snapshot task failed"))?;
+
+    match result {
+        Ok((path, len, digest)) => {
+            info!("snapshot written to {} ({len} bytes)", path.display());
+            Ok(Json(json!({
+                "path": path,
+                "bytes": len,
+                "sha256": digest,
+            })))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            Err((StatusCode::CONFLICT, "Snapshot path already exists").into())
+        }
+        Err(e) => {
+            error!("failed to write snapshot: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to write snapshot",
+            )
+                .into())
+        }
+    }
+}
+
+// 描述相同的 topic 分组, 大组在前; 只包含建立索引后新建或编辑过的 topic
+async fn duplicates_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+    page: Pagination,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        Ok(())
+    })?;
+
+    let mut groups: Vec<(String, Vec<String>)> = state
+        .scan_all::<search::DescGroup>()?
+        .into_iter()
+        .filter(|(_, group)| group.0.len() > 1)
+        .map(|(hash, group)| {
+            let mut topics: Vec<String> = group.0.into_iter().collect();
+            topics.sort();
+            (String::from_utf8_lossy(&hash).into_owned(), topics)
+        })
+        .collect();
+    groups.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+    let total: usize = groups.len();
+    let groups: Vec<serde_json::Value> = page
+        .apply(groups)
+        .into_iter()
+        .map(|(hash, topics)| json!({ "hash": hash, "topics": topics }))
+        .collect();
+    Ok(Json(json!({ "total": total, "groups": groups })))
+}
+
+// 检查反范式的名称列表是否指向不存在的 topic, 以及作者已不存在的 topic
+async fn integrity_handler(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<Arc<Mutex<OptionalToken>>>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    const SAMPLE: usize = 100;
+    let admin_uid: u64 = token.lock().await.auth_admin()?;
+    let top: Top = state.transaction(|helper| {
+        helper
+            .get_or_not_found::<_, UserStatus>(&admin_uid)?
+            .as_admin()?;
+        Ok(helper.get(&"")?.unwrap_or_default())
+    })?;
+
+    let users: Vec<(u64, UserStatus)> = state
+        .scan_all::<UserStatus>()?
+        .into_iter()
+        .filter_map(|(key, status)| Some((u64::from_le_bytes(key.try_into().ok()?), status)))
+        .collect();
+    let topics: HashMap<String, Topic> = state
+        .scan_all::<Topic>()?
+        .into_iter()
+        .map(|(key, topic)| (String::from_utf8_lossy(&key).into_owned(), topic))
+        .collect();
+    let uids: HashSet<u64> = users.iter().map(|(uid, _)| *uid).collect();
+
+    let dangling_user_refs: Vec<serde_json::Value> = users
+        .iter()
+        .flat_map(|(uid, status)| {
+            status
+                .data()
+                .topics
+                .iter()
+                .filter(|name| !topics.contains_key(*name))
+                .map(move |name| json!({ "uid": uid.to_string(), "topic": name }))
+        })
+        .collect();
+    let dangling_top_refs: Vec<&String> = top
+        .0
+        .iter()
+        .filter(|name| !topics.contains_key(*name))
+        .collect();
+    let orphaned_topics: Vec<serde_json::Value> = topics
+        .iter()
+        .filter(|(_, topic)| !uids.contains(&topic.author))
+        .map(|(name, topic)| json!({ "topic": name, "author": topic.author.to_string() }))
+        .collect();
+
+    Ok(Json(json!({
+        "dangling_user_refs": {
+            "count": dangling_user_refs.len(),
+            "sample": &dangling_user_refs[..dangling_user_refs.len().min(SAMPLE)],
+        },
+        "dangling_top_refs": {
+            "count": dangling_top_refs.len(),
+            "sample": &dangling_top_refs[..dangling_top_refs.len().min(SAMPLE)],
+        },
+        "orphaned_topics": {
+            "count": orphaned_topics.len(),
+            "sample": &orphaned_topics[..orphaned_topics.len().min(SAMPLE)],
+        },
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ConnectInfo;
+    use axum::http::{Method, Request};
+    use tagme::models::LoginPolicy;
+    use tower::ServiceExt;
+
+    // 临时数据库, 不限流, 其余取配置默认值
+    fn test_state() -> AppState {
+        AppState {
+            db: OnceLock::from(sled::Config::new().temporary(true).open().unwrap()),
+            oauth_client_id: String::new(),
+            oauth_client_secrets: String::new(),
+            oauth_redirect_uri: String::new(),
+            base_path: String::new(),
+            effective_config: json!({}),
+            user_limiter: RateLimiter::new(0),
+            young_limiter: RateLimiter::new(0),
+            new_account_grace_secs: 0,
+            anonymous_vote_weight: 1,
+            require_tag: false,
+            cookie_auth: false,
+            ip_limiter: RateLimiter::new(0),
+            trusted_proxies: HashSet::new(),
+            login_policy: LoginPolicy::default(),
+            api_keys: HashMap::new(),
+            page_config: PageConfig {
+                default_size: 50,
+                max_size: 200,
+            },
+            tag_limits: TagLimits::default(),
+            topic_name_pattern: None,
+            lenient_decode: false,
+            http: reqwest::Client::new(),
+            tag_blocklist: HashSet::new(),
+            github_webhook_secret: String::new(),
+            snapshot_dir: None,
+            response_cache: ResponseCache::new(std::time::Duration::ZERO, 0),
+            request_slots: None,
+            topic_events: tokio::sync::broadcast::channel(16).0,
+        }
+    }
+
+    struct TestApp {
+        state: Arc<AppState>,
+        router: axum::Router,
+    }
+
+    impl TestApp {
+        fn new(state: AppState) -> Self {
+            Self::with_cors(state, cors_layer(&[], false, 0))
+        }
+
+        fn with_cors(state: AppState, cors: CorsLayer) -> Self {
+            let state = Arc::new(state);
+            let router = router(state.clone(), cors);
+            Self { state, router }
+        }
+
+        fn default() -> Self {
+            Self::new(test_state())
+        }
+
+        /// Inserts a user that is not a young account and returns a token for it.
+        fn user(&self, uid: u64, admin: bool) -> Token {
+            let mut user_status = UserStatus::default();
+            user_status.data_mut().login = format!("user{uid}");
+            user_status.data_mut().created_at = 0;
+            if admin {
+                user_status = UserStatus::Admin(user_status.into_data());
+            }
+            self.state
+                .transaction(|helper| helper.insert(&uid, &user_status))
+                .unwrap();
+            Token::new(uid)
+        }
+
+        async fn send(&self, request: Request<Body>) -> Response<Body> {
+            self.router.clone().oneshot(request).await.unwrap()
+        }
+
+        async fn call(
+            &self,
+            method: Method,
+            uri: &str,
+            token: Option<&Token>,
+            body: Option<serde_json::Value>,
+        ) -> (StatusCode, serde_json::Value) {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, token.to_string());
+            }
+            let request = match body {
+                Some(body) => request
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string())),
+                None => request.body(Body::empty()),
+            };
+            let response = self.send(request.unwrap()).await;
+            (response.status(), body_json(response).await)
+        }
+
+        async fn get(&self, uri: &str, token: Option<&Token>) -> (StatusCode, serde_json::Value) {
+            self.call(Method::GET, uri, token, None).await
+        }
+
+        async fn post(
+            &self,
+            uri: &str,
+            token: Option<&Token>,
+            body: serde_json::Value,
+        ) -> (StatusCode, serde_json::Value) {
+            self.call(Method::POST, uri, token, Some(body)).await
+        }
+
+        async fn create_topic(&self, name: &str, token: &Token, tags: &[&str]) {
+            let (status, body) = self
+                .post(
+                    &format!("/topic/{name}"),
+                    Some(token),
+                    json!({ "description": format!("about {name}"), "tags": tags }),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+        }
+
+        async fn make_private(&self, name: &str, owner: &Token) {
+            let (status, body) = self
+                .post(
+                    "/me/topics/visibility",
+                    Some(owner),
+                    json!({ "names": [name], "visibility": "private" }),
+                )
+                .await;
+            assert_eq!(body[name], "updated", "{status} {body}");
+        }
+
+        fn topic(&self, name: &str) -> Topic {
+            self.state
+                .transaction(|helper| helper.get_or_not_found(&name.to_string()))
+                .unwrap()
+        }
+
+        /// Changes the stored topic directly, bypassing the handlers.
+        fn edit_topic(&self, name: &str, edit: impl Fn(&mut Topic)) {
+            let name = name.to_string();
+            self.state
+                .transaction(|helper| {
+                    let mut topic: Topic = helper.get_or_not_found(&name)?;
+                    edit(&mut topic);
+                    helper.insert(&name, &topic)
+                })
+                .unwrap();
+        }
+    }
+
+    // 非 JSON 的响应体作为字符串返回
+    async fn body_json(response: Response<Body>) -> serde_json::Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| json!(String::from_utf8_lossy(&bytes).into_owned()))
+    }
+
+    #[tokio::test]
+    async fn unvote_removes_own_vote_down_to_zero() {
+        let app = TestApp::default();
+        let (owner, voter, other) = (app.user(1, false), app.user(2, false), app.user(3, false));
+        app.create_topic("t", &owner, &[]).await;
+        app.post("/add/tag/t", Some(&owner), json!({ "tag": "rust" }))
+            .await;
+        let (_, body) = app
+            .post("/add/tag/t", Some(&voter), json!({ "tag": "rust" }))
+            .await;
+        assert_eq!(body["changed"]["new_count"], 2);
+
+        // 没投过票的用户不能减少计数
+        let (status, body) = app
+            .post("/del/vote/t", Some(&other), json!({ "tag": "rust" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["changed"]["action"], "none");
+        assert_eq!(app.topic("t").tags["rust"], 2);
+
+        let (_, body) = app
+            .post("/del/vote/t", Some(&voter), json!({ "tag": "rust" }))
+            .await;
+        assert_eq!(body["changed"]["action"], "unvote");
+        assert_eq!(body["changed"]["new_count"], 1);
+        // 同一用户重复撤回不再生效
+        let (_, body) = app
+            .post("/del/vote/t", Some(&voter), json!({ "tag": "rust" }))
+            .await;
+        assert_eq!(body["changed"]["action"], "none");
+
+        let (_, body) = app
+            .post("/del/vote/t", Some(&owner), json!({ "tag": "rust" }))
+            .await;
+        assert_eq!(body["changed"]["action"], "unvote");
+        assert!(body["changed"].get("new_count").is_none());
+        let topic = app.topic("t");
+        assert!(!topic.tags.contains_key("rust") && !topic.voters.contains_key("rust"));
+    }
+
+    #[tokio::test]
+    async fn unvote_never_goes_negative() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("t", &owner, &[]).await;
+        app.post("/add/tag/t", Some(&owner), json!({ "tag": "rust" }))
+            .await;
+        for _ in 0..3 {
+            app.post("/del/vote/t", Some(&owner), json!({ "tag": "rust" }))
+                .await;
+        }
+        assert!(app.topic("t").tags.is_empty());
+
+        // 只存在于 pending 的 tag 不受影响
+        let voter = app.user(2, false);
+        app.post("/add/tag/t", Some(&voter), json!({ "tag": "go" }))
+            .await;
+        let (_, body) = app
+            .post("/del/vote/t", Some(&voter), json!({ "tag": "go" }))
+            .await;
+        assert_eq!(body["changed"]["action"], "none");
+        assert!(app.topic("t").pending_tags.contains("go"));
+    }
+
+    #[tokio::test]
+    async fn unvote_requires_login() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("t", &owner, &[]).await;
+        app.post("/add/tag/t", Some(&owner), json!({ "tag": "rust" }))
+            .await;
+        let (status, _) = app
+            .post("/del/vote/t", None, json!({ "tag": "rust" }))
+            .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (_, body) = app.get("/topic/t", None).await;
+        assert_eq!(body["tags"]["rust"], 1);
+    }
+
+    #[tokio::test]
+    async fn repeated_votes_count_once() {
+        let app = TestApp::default();
+        let (owner, a, b) = (app.user(1, false), app.user(2, false), app.user(3, false));
+        app.create_topic("t", &owner, &["rust"]).await;
+
+        // 创建时作者已经投过一票
+        let (_, body) = app
+            .post("/add/tag/t", Some(&owner), json!({ "tag": "rust" }))
+            .await;
+        assert_eq!(body["changed"]["new_count"], 1);
+        for _ in 0..2 {
+            let (_, body) = app
+                .post("/add/tag/t", Some(&a), json!({ "tag": "rust" }))
+                .await;
+            assert_eq!(body["changed"]["new_count"], 2);
+        }
+        let (_, body) = app
+            .post("/add/tag/t", Some(&b), json!({ "tag": "rust" }))
+            .await;
+        assert_eq!(body["changed"]["new_count"], 3);
+    }
+
+    #[tokio::test]
+    async fn approved_and_imported_tags_carry_the_owner_vote() {
+        let app = TestApp::default();
+        let (owner, other) = (app.user(1, false), app.user(2, false));
+        app.create_topic("t", &owner, &[]).await;
+        app.post("/add/tag/t", Some(&other), json!({ "tag": "go" }))
+            .await;
+        app.post("/approve-all/tag/t", Some(&owner), json!({}))
+            .await;
+        let request = Request::post("/import/tag/t")
+            .header(header::AUTHORIZATION, owner.to_string())
+            .header(header::CONTENT_TYPE, "text/csv")
+            .body(Body::from("zig,3\n"))
+            .unwrap();
+        assert_eq!(app.send(request).await.status(), StatusCode::OK);
+
+        for (tag, count) in [("go", 1), ("zig", 3)] {
+            let (_, body) = app
+                .post("/add/tag/t", Some(&owner), json!({ "tag": tag }))
+                .await;
+            assert_eq!(body["changed"]["new_count"], count, "{tag}");
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_op_requires_an_admin() {
+        let app = TestApp::default();
+        let (admin, user) = (app.user(1, true), app.user(2, false));
+        app.user(3, false);
+
+        for query in ["", "?op=3&deop=3"] {
+            let (status, _) = app
+                .call(
+                    Method::POST,
+                    &format!("/private/admin{query}"),
+                    Some(&admin),
+                    None,
+                )
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{query}");
+        }
+        let (status, _) = app
+            .call(Method::POST, "/private/admin?op=2", None, None)
+            .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = app
+            .call(Method::POST, "/private/admin?op=2", Some(&user), None)
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["kind"], "admin_required");
+        let (status, _) = app
+            .call(Method::POST, "/private/admin?deop=1", Some(&admin), None)
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = app
+            .call(Method::POST, "/private/admin?op=3", Some(&admin), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = app.get("/user/3", None).await;
+        assert_eq!(body["status"], "Admin");
+    }
+
+    #[tokio::test]
+    async fn move_tag_sums_counts() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("from", &owner, &["rust"]).await;
+        app.create_topic("to", &owner, &["rust"]).await;
+        for uid in 2..=4 {
+            let voter = app.user(uid, false);
+            app.post("/add/tag/from", Some(&voter), json!({ "tag": "rust" }))
+                .await;
+        }
+
+        let (status, body) = app
+            .post(
+                "/move/tag/from",
+                Some(&owner),
+                json!({ "tag": "rust", "to_topic": "to" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["topic"]["tags"]["rust"], 5);
+        assert_eq!(
+            body["changed"],
+            json!({ "tag": "rust", "action": "move", "new_count": 5 })
+        );
+        assert!(app.topic("from").tags.is_empty());
+        assert_eq!(app.topic("to").voters["rust"].len(), 4);
+    }
+
+    #[tokio::test]
+    async fn move_tag_saturates() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("from", &owner, &[]).await;
+        app.create_topic("to", &owner, &["rust"]).await;
+        let request = Request::post("/import/tag/from")
+            .header(header::AUTHORIZATION, owner.to_string())
+            .header(header::CONTENT_TYPE, "text/csv")
+            .body(Body::from(format!("rust,{}\n", u32::MAX)))
+            .unwrap();
+        assert_eq!(app.send(request).await.status(), StatusCode::OK);
+
+        let (status, body) = app
+            .post(
+                "/move/tag/from",
+                Some(&owner),
+                json!({ "tag": "rust", "to_topic": "to" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["topic"]["tags"]["rust"], u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn blocked_tags_are_rejected() {
+        let mut state = test_state();
+        state.tag_blocklist = HashSet::from([fold_tag("Spam Tag")]);
+        let app = TestApp::new(state);
+        let (admin, owner, user) = (app.user(1, true), app.user(2, false), app.user(3, false));
+        app.create_topic("t", &owner, &[]).await;
+
+        for tag in ["spamtag", "SPAM tag"] {
+            let (status, body) = app
+                .post("/add/tag/t", Some(&user), json!({ "tag": tag }))
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{tag}");
+            assert_eq!(body["error"]["kind"], "tag_blocked");
+        }
+        let (status, body) = app
+            .post(
+                "/topic/other",
+                Some(&owner),
+                json!({ "description": "", "tags": ["ok", "Spam  Tag"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["kind"], "tag_blocked");
+        let (status, _) = app.get("/topic/other", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // 管理员可以绕过
+        app.create_topic("admin", &admin, &["spamtag"]).await;
+        let (status, _) = app
+            .post("/add/tag/t", Some(&admin), json!({ "tag": "spamtag" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_allows_patch_and_embed_header() {
+        let origins = ["https://app.example.com".to_string()];
+        let app = TestApp::with_cors(test_state(), cors_layer(&origins, true, 600));
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/user")
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "x-embed-token,if-none-match",
+            )
+            .body(Body::empty())
+            .unwrap();
+        let response = app.send(request).await;
+        let headers = response.headers();
+        let get = |name: header::HeaderName| headers[name].to_str().unwrap().to_string();
+        assert!(get(header::ACCESS_CONTROL_ALLOW_METHODS).contains("PATCH"));
+        let allowed = get(header::ACCESS_CONTROL_ALLOW_HEADERS);
+        assert!(allowed.contains("x-embed-token") && allowed.contains("if-none-match"));
+        assert_eq!(get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS), "true");
+        assert_eq!(get(header::ACCESS_CONTROL_MAX_AGE), "600");
+
+        let request = Request::get("/health")
+            .header(header::ORIGIN, "https://app.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.send(request).await;
+        let exposed = response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(exposed.contains("x-ratelimit-remaining"));
+    }
+
+    #[tokio::test]
+    async fn revoked_tokens_are_rejected() {
+        let app = TestApp::default();
+        let (admin, user) = (app.user(1, true), app.user(2, false));
+        let (status, _) = app.get("/user", Some(&user)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = app
+            .call(Method::DELETE, "/user/2", Some(&admin), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.get("/user", Some(&user)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // 登出时已经存在的 token 全部失效, 包括同一秒内签发的
+        let same_second = Token::new(1);
+        let (status, _) = app.call(Method::POST, "/logout", Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK);
+        for token in [&admin, &same_second] {
+            let (status, _) = app.get("/user", Some(token)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+        // 登出之后重新登录拿到的 token 有效, 刷新后也一样
+        let relogin = Token::issue(&app.state, 1).unwrap();
+        let response = app
+            .send(
+                Request::get("/user")
+                    .header(header::AUTHORIZATION, relogin.to_string())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let refreshed = Token::try_from(response.headers()).unwrap();
+        let (status, _) = app.get("/user", Some(&refreshed)).await;
+        assert_eq!(status, StatusCode::OK);
+        // 没有 token 时登出也成功
+        let (status, _) = app.call(Method::POST, "/logout", None, None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn single_field_errors_keep_their_kind() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &[]).await;
+
+        let long_tag = "x".repeat(65);
+        let (status, body) = app
+            .post("/add/tag/rust", Some(&owner), json!({ "tag": long_tag }))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["kind"], "tag_too_long");
+        assert_eq!(body["error"]["fields"][0]["field"], "tag");
+
+        // 多个字段同时出错时逐条列出
+        let (status, body) = app
+            .post(
+                "/topic/go",
+                Some(&owner),
+                json!({ "description": "x".repeat(tagme::models::MAX_DESCRIPTION_CHARS + 1), "tags": [long_tag] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["kind"], "validation_failed");
+        let kinds: Vec<&str> = body["error"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["description_too_long", "tag_too_long"]);
+    }
+
+    #[tokio::test]
+    async fn embed_token_reads_private_topic_uncached() {
+        let mut state = test_state();
+        state.response_cache = ResponseCache::new(std::time::Duration::from_secs(60), 16);
+        let app = TestApp::new(state);
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &[]).await;
+        app.create_topic("go", &owner, &[]).await;
+        app.make_private("rust", &owner).await;
+
+        let (status, body) = app
+            .post("/token/embed", Some(&owner), json!({ "topic": "rust" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let embed = body["token"].as_str().unwrap().to_string();
+
+        let (status, _) = app.get("/topic/rust", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        for _ in 0..2 {
+            let request = Request::get("/topic/rust")
+                .header("X-Embed-Token", &embed)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.send(request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(!response.headers().contains_key("X-Cache"));
+        }
+        let response = app
+            .send(
+                Request::get(format!("/topic/rust?embed={embed}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key("X-Cache"));
+        // 嵌入 token 只对签发时的 topic 有效
+        let (status, _) = app.get(&format!("/topic/go?embed={embed}"), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let response = app
+            .send(Request::get("/topic/go").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(response.headers()["X-Cache"], "MISS");
+    }
+
+    #[tokio::test]
+    async fn prune_removes_stale_topics_including_private_and_legacy() {
+        let app = TestApp::default();
+        let (admin, owner) = (app.user(1, true), app.user(2, false));
+        for name in ["old", "hidden", "legacy", "unknown", "fresh", "tagged"] {
+            let tags: &[&str] = if name == "tagged" { &["rust"] } else { &[] };
+            app.create_topic(name, &owner, tags).await;
+        }
+        app.make_private("hidden", &owner).await;
+        let long_ago = now() - 90 * 86400;
+        app.state
+            .transaction(|helper| {
+                for (name, created_at, updated_at) in [
+                    ("old", long_ago, long_ago),
+                    ("hidden", long_ago, long_ago),
+                    ("legacy", long_ago, 0),
+                    ("unknown", 0, 0),
+                    ("tagged", long_ago, long_ago),
+                ] {
+                    let name = name.to_string();
+                    let mut topic: Topic = helper.get_or_not_found(&name)?;
+                    (topic.created_at, topic.updated_at) = (created_at, updated_at);
+                    helper.insert(&name, &topic)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let prune = |query: &'static str| {
+            let uri = format!("/private/admin/prune{query}");
+            let app = &app;
+            let admin = &admin;
+            async move { app.call(Method::POST, &uri, Some(admin), None).await }
+        };
+        for query in ["?min_age_days=-1", "?min_age_days=4294967296"] {
+            let (status, _) = prune(query).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{query}");
+        }
+        let (status, body) = prune("?min_age_days=4294967295").await;
+        assert_eq!((status, body["pruned"].as_u64()), (StatusCode::OK, Some(0)));
+
+        let (_, body) = prune("").await;
+        assert_eq!(body["pruned"], 3);
+        for name in ["old", "hidden", "legacy"] {
+            let (status, _) = app.get(&format!("/topic/{name}"), Some(&owner)).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{name}");
+        }
+        for name in ["unknown", "fresh", "tagged"] {
+            let (status, _) = app.get(&format!("/topic/{name}"), Some(&owner)).await;
+            assert_eq!(status, StatusCode::OK, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn user_topics_report_total_beyond_page() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        for i in 0..3 {
+            app.create_topic(&format!("t{i}"), &owner, &[]).await;
+        }
+        app.make_private("t2", &owner).await;
+
+        let (_, body) = app.get("/user?limit=2", Some(&owner)).await;
+        assert_eq!(body["topics"].as_array().unwrap().len(), 2);
+        assert_eq!(body["topics_total"], 3);
+        // 他人看到的总数不含私有 topic
+        let (_, body) = app.get("/user/1?limit=1&offset=1", None).await;
+        assert_eq!(body["topics"], json!(["t1"]));
+        assert_eq!(body["topics_total"], 2);
+    }
+
+    #[tokio::test]
+    async fn voted_follows_synonyms_and_hides_private_topics() {
+        let app = TestApp::default();
+        let (admin, owner, voter) = (app.user(1, true), app.user(2, false), app.user(3, false));
+        let (status, _) = app
+            .post(
+                "/private/admin/tag-synonym",
+                Some(&admin),
+                json!({ "from": "js", "to": "javascript" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        app.create_topic("web", &owner, &["javascript"]).await;
+        app.post("/add/tag/web", Some(&voter), json!({ "tag": "js" }))
+            .await;
+
+        let (status, body) = app.get("/voted/topic/web?tag=js", Some(&voter)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["voted"], true);
+
+        app.make_private("web", &owner).await;
+        let (status, _) = app.get("/voted/topic/web?tag=js", Some(&voter)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = app.get("/voted/topic/web?tag=js", Some(&owner)).await;
+        assert_eq!(
+            (status, body["voted"].clone()),
+            (StatusCode::OK, json!(true))
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_moves_topics_votes_and_featured_entry() {
+        let app = TestApp::default();
+        let (admin, from, to) = (app.user(1, true), app.user(2, false), app.user(3, false));
+        let other = app.user(4, false);
+        app.create_topic("mine", &from, &[]).await;
+        app.create_topic("theirs", &to, &[]).await;
+        app.create_topic("shared", &other, &["rust", "go"]).await;
+        for voter in [&from, &to] {
+            app.post("/add/tag/shared", Some(voter), json!({ "tag": "rust" }))
+                .await;
+        }
+        app.post("/add/tag/shared", Some(&from), json!({ "tag": "go" }))
+            .await;
+        for uid in [4, 2] {
+            let (status, _) = app
+                .call(
+                    Method::POST,
+                    &format!("/private/admin/feature/author/{uid}"),
+                    Some(&admin),
+                    None,
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let (status, body) = app
+            .post(
+                "/private/admin/merge-users",
+                Some(&admin),
+                json!({ "from": 2, "to": 3 }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["topics"], json!(["theirs", "mine"]));
+        assert_eq!(app.topic("mine").author, 3);
+
+        let shared = app.topic("shared");
+        for tag in ["rust", "go"] {
+            assert!(!shared.voters[tag].contains(&2), "{tag}");
+            assert!(shared.voters[tag].contains(&3), "{tag}");
+        }
+        // 两个账号都投过 rust, 合并后只算一票
+        assert_eq!((shared.tags["rust"], shared.tags["go"]), (2, 2));
+
+        let featured: FeaturedAuthors = app
+            .state
+            .transaction(|helper| helper.get_or_not_found(&""))
+            .unwrap();
+        assert_eq!(featured.0, [4, 3]);
+        let (status, _) = app.get("/user/2", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn topic_fields_selects_a_subset() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &["lang"]).await;
+
+        let (status, body) = app.get("/topic/rust?fields=tags", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "tags": { "lang": 1 } }));
+        let (status, body) = app.get("/topic/rust?fields=tags,nope", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["kind"], "unknown_field");
+    }
+
+    #[tokio::test]
+    async fn not_found_names_the_entity() {
+        let app = TestApp::default();
+        let (status, body) = app.get("/topic/missing", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["kind"], "topic_not_found");
+        assert_eq!(body["error"]["message"], "Topic 'missing' not found");
+
+        let (status, body) = app.get("/user/42", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["kind"], "user_not_found");
+        assert_eq!(body["error"]["message"], "User '42' not found");
+    }
+
+    #[tokio::test]
+    async fn synonym_votes_land_on_canonical_tag() {
+        let app = TestApp::default();
+        let (admin, owner, voter) = (app.user(1, true), app.user(2, false), app.user(3, false));
+        app.post(
+            "/private/admin/tag-synonym",
+            Some(&admin),
+            json!({ "from": "js", "to": "javascript" }),
+        )
+        .await;
+        app.create_topic("web", &owner, &["javascript"]).await;
+
+        let (status, body) = app
+            .post("/add/tag/web", Some(&voter), json!({ "tag": "js" }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let topic = app.topic("web");
+        assert_eq!(topic.tags.get("javascript"), Some(&2));
+        assert!(!topic.tags.contains_key("js"));
+    }
+
+    #[tokio::test]
+    async fn tag_mutations_report_the_change() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &["lang"]).await;
+
+        let (_, body) = app
+            .post("/add/tag/rust", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+        assert_eq!(
+            body["changed"],
+            json!({ "tag": "lang", "action": "vote", "new_count": 2 })
+        );
+        let (_, body) = app
+            .post("/add/tag/rust", Some(&voter), json!({ "tag": "fast" }))
+            .await;
+        assert_eq!(
+            body["changed"],
+            json!({ "tag": "fast", "action": "suggest" })
+        );
+        // 作者再提交待审核的 tag 即为批准
+        let (_, body) = app
+            .post("/add/tag/rust", Some(&owner), json!({ "tag": "fast" }))
+            .await;
+        assert_eq!(
+            body["changed"],
+            json!({ "tag": "fast", "action": "add", "new_count": 1 })
+        );
+        assert_eq!(body["topic"]["tags"]["fast"], 1);
+        let (_, body) = app
+            .post("/del/tag/rust", Some(&owner), json!({ "tag": "lang" }))
+            .await;
+        assert_eq!(
+            body["changed"],
+            json!({ "tag": "lang", "action": "delete" })
+        );
+        assert!(body["topic"]["tags"].get("lang").is_none());
+    }
+
+    #[tokio::test]
+    async fn user_rate_budget_is_per_user() {
+        let mut state = test_state();
+        state.user_limiter = RateLimiter::new(2);
+        let app = TestApp::new(state);
+        let (owner, spammer, other) = (app.user(1, false), app.user(2, false), app.user(3, false));
+        app.create_topic("rust", &owner, &["lang"]).await;
+
+        for _ in 0..2 {
+            let (status, _) = app
+                .post("/add/tag/rust", Some(&spammer), json!({ "tag": "lang" }))
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, body) = app
+            .post("/add/tag/rust", Some(&spammer), json!({ "tag": "lang" }))
+            .await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["error"]["kind"], "rate_limited");
+        let (status, _) = app
+            .post("/add/tag/rust", Some(&other), json!({ "tag": "lang" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn export_contains_own_topics() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &["lang"]).await;
+        app.create_topic("go", &voter, &["fast"]).await;
+        app.create_topic("secret", &voter, &["hidden"]).await;
+        app.post("/add/tag/rust", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+        app.post("/add/tag/go", Some(&owner), json!({ "tag": "fast" }))
+            .await;
+        app.post("/add/tag/secret", Some(&owner), json!({ "tag": "hidden" }))
+            .await;
+        app.make_private("secret", &voter).await;
+
+        let (status, _) = app.get("/me/export", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let response = app
+            .send(
+                Request::get("/me/export")
+                    .header(header::AUTHORIZATION, owner.to_string())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"tagme-1.json\""
+        );
+        let body = body_json(response).await;
+        assert_eq!(body["user"]["topics"], json!(["rust"]));
+        assert_eq!(body["topics"]["rust"]["description"], "about rust");
+        assert_eq!(body["topics"]["rust"]["tags"]["lang"], 2);
+        // 不含其他用户的投票, 只有自己的; 别人的私有 topic 不出现
+        assert!(body["topics"]["rust"].get("voters").is_none());
+        assert_eq!(body["votes"], json!({ "go": ["fast"], "rust": ["lang"] }));
+    }
+
+    #[tokio::test]
+    async fn motd_is_set_served_and_cleared() {
+        let app = TestApp::default();
+        let (admin, user) = (app.user(1, true), app.user(2, false));
+        let motd = json!({ "message": "maintenance at 10:00", "level": "warn" });
+
+        let (status, _) = app
+            .post("/private/admin/motd", Some(&user), motd.clone())
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = app
+            .post("/private/admin/motd", Some(&admin), motd.clone())
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = app.get("/motd", None).await;
+        assert_eq!((status, body), (StatusCode::OK, motd));
+
+        app.post(
+            "/private/admin/motd",
+            Some(&admin),
+            json!({ "message": "" }),
+        )
+        .await;
+        let (_, body) = app.get("/motd", None).await;
+        assert_eq!(body, json!(null));
+    }
+
+    #[tokio::test]
+    async fn topic_paths_decode_and_reject_oversize() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("c%23", &owner, &[]).await;
+        assert_eq!(app.topic("c#").description, "about c%23");
+        app.create_topic("lang/rust/async", &owner, &[]).await;
+        let (status, _) = app.get("/topic/lang/rust/async", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = app.get(&format!("/topic/{}", "x".repeat(129)), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["kind"], "topic_too_long");
+        for path in ["/topic/a//b", "/topic/a/../b", "/topic/%20"] {
+            let (status, body) = app.get(path, None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{path}");
+            assert_eq!(body["error"]["kind"], "topic_invalid", "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_headers_count_down_and_refill() {
+        let mut state = test_state();
+        state.ip_limiter = RateLimiter::new(60); // 每秒回一个
+        state.trusted_proxies.insert([127, 0, 0, 1].into());
+        let app = TestApp::new(state);
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &["lang"]).await;
+
+        let proxy = ConnectInfo(std::net::SocketAddr::from(([127, 0, 0, 1], 40000)));
+        let vote = || {
+            Request::post("/add/tag/rust")
+                .header("X-Forwarded-For", "203.0.113.7")
+                .header(header::CONTENT_TYPE, "application/json")
+                .extension(proxy)
+                .body(Body::from(r#"{"tag":"lang"}"#))
+                .unwrap()
+        };
+        let remaining = |response: &Response<Body>| -> u32 {
+            response.headers()["X-RateLimit-Remaining"]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        let first = app.send(vote()).await;
+        assert_eq!(first.headers()["X-RateLimit-Limit"], "60");
+        assert_eq!(remaining(&first), 59);
+        let second = app.send(vote()).await;
+        assert_eq!(remaining(&second), 58);
+        assert_eq!(second.headers()["X-RateLimit-Reset"], "2");
+
+        tokio::time::sleep(std::time::Duration::from_millis(2100)).await;
+        let health = Request::get("/health")
+            .header("X-Forwarded-For", "203.0.113.7")
+            .extension(proxy)
+            .body(Body::empty())
+            .unwrap();
+        let refilled = app.send(health).await;
+        assert_eq!(remaining(&refilled), 60);
+        assert_eq!(refilled.headers()["X-RateLimit-Reset"], "0");
+    }
+
+    #[tokio::test]
+    async fn create_topic_with_initial_tags() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        let (status, body) = app
+            .post(
+                "/topic/rust",
+                Some(&owner),
+                json!({ "description": "a language", "tags": ["lang", "fast", "safe"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            body["tags"],
+            json!({ "fast": 1, "lang": 1, "safe": 1 }),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn voted_tracks_vote_and_unvote() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &["lang"]).await;
+
+        let (_, body) = app.get("/voted/topic/rust?tag=lang", Some(&voter)).await;
+        assert_eq!(body["voted"], false);
+        app.post("/add/tag/rust", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+        let (_, body) = app.get("/voted/topic/rust?tag=lang", Some(&voter)).await;
+        assert_eq!(body["voted"], true);
+        app.post("/del/vote/rust", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+        let (_, body) = app.get("/voted/topic/rust?tag=lang", Some(&voter)).await;
+        assert_eq!(body["voted"], false);
+        let (_, body) = app.get("/voted/topic/rust?tag=lang", None).await;
+        assert_eq!(body["voted"], false);
+    }
+
+    #[tokio::test]
+    async fn tags_serialize_in_stable_order() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &["b", "c", "a"]).await;
+        app.post("/add/tag/rust", Some(&voter), json!({ "tag": "c" }))
+            .await;
+        for tag in ["z", "y"] {
+            app.post("/add/tag/rust", Some(&voter), json!({ "tag": tag }))
+                .await;
+        }
+
+        let (_, body) = app.get("/topic/rust", Some(&owner)).await;
+        let keys: Vec<&String> = body["tags"].as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["c", "a", "b"]);
+        assert_eq!(body["pending_tags"], json!(["y", "z"]));
+    }
+
+    #[tokio::test]
+    async fn unavailable_until_database_opens() {
+        let mut state = test_state();
+        let db = state.db.take().unwrap();
+        let app = TestApp::new(state);
+
+        let response = app
+            .send(Request::get("/top").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+        let (status, _) = app.get("/health", None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        app.state.db.set(db).unwrap();
+        let (status, _) = app.get("/top", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.get("/health", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn api_keys_are_scoped() {
+        let mut state = test_state();
+        state.api_keys = HashMap::from([
+            ("read-key".to_string(), ApiScope::Read),
+            ("write-key".to_string(), ApiScope::Write(900)),
+        ]);
+        let app = TestApp::new(state);
+        ensure_service_users(app.state.db(), &app.state.api_keys).unwrap();
+        let with_key = |key: &str, method: Method, uri: &str, body: serde_json::Value| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("X-Api-Key", key)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let topic = json!({ "description": "from a bot" });
+
+        let response = app
+            .send(with_key(
+                "write-key",
+                Method::POST,
+                "/topic/bot",
+                topic.clone(),
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(app.topic("bot").author, 900);
+        let response = app
+            .send(with_key(
+                "write-key",
+                Method::POST,
+                "/private/admin/motd",
+                json!({ "message": "hi" }),
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .send(with_key(
+                "read-key",
+                Method::POST,
+                "/topic/read",
+                topic.clone(),
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app
+            .send(with_key("read-key", Method::GET, "/topic/bot", json!(null)))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .send(with_key(
+                "wrong-key",
+                Method::GET,
+                "/topic/bot",
+                json!(null),
+            ))
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn author_stats_sum_public_topics() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &["lang", "fast"]).await;
+        app.create_topic("go", &owner, &["lang"]).await;
+        app.post("/add/tag/rust", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+
+        let (status, body) = app.get("/stats/user/1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({ "topic_count": 2, "total_tags_received": 4, "most_popular_topic": "rust" })
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_if_match_blocks_delete() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &["lang"]).await;
+        let etag = |response: &Response<Body>| response.headers()[header::ETAG].clone();
+        let read = || Request::get("/topic/rust").body(Body::empty()).unwrap();
+        let delete = |etag: HeaderValue| {
+            Request::delete("/topic/rust")
+                .header(header::AUTHORIZATION, owner.to_string())
+                .header(header::IF_MATCH, etag)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let stale = etag(&app.send(read()).await);
+        app.post("/add/tag/rust", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+        let response = app.send(delete(stale)).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(body_json(response).await["error"]["kind"], "topic_changed");
+
+        let fresh = etag(&app.send(read()).await);
+        let response = app.send(delete(fresh)).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let (status, _) = app.get("/topic/rust", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn create_reports_every_invalid_field() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        let (status, body) = app
+            .post(
+                &format!("/topic/{}", "r".repeat(129)),
+                Some(&owner),
+                json!({
+                    "description": "x".repeat(tagme::models::MAX_DESCRIPTION_CHARS + 1),
+                    "tags": ["ok", ""],
+                    "url": "javascript:alert(1)",
+                }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body["error"]["fields"],
+            json!([
+                { "field": "topic", "kind": "topic_too_long", "message": "Topic is too long" },
+                { "field": "description", "kind": "description_too_long", "message": "Description is too long" },
+                { "field": "tags[1]", "kind": "tag_empty", "message": "Tag is empty" },
+                { "field": "url", "kind": "url_invalid", "message": "URL is invalid" },
+            ])
+        );
+        let (status, _) = app.get("/topic/rust", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn version_reports_build_metadata() {
+        let app = TestApp::default();
+        let (status, body) = app.get("/version", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["version"].as_str().is_some_and(|v| !v.is_empty()));
+        assert!(body["git_sha"].is_string() && body["rustc"].is_string());
+    }
+
+    #[tokio::test]
+    async fn pending_tags_page_with_total() {
+        let app = TestApp::default();
+        let (owner, other) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &[]).await;
+        app.edit_topic("rust", |topic| {
+            topic.pending_tags = (0..100).map(|i| format!("tag{i:03}")).collect();
+        });
+
+        let (status, _) = app.get("/pending/topic/rust", Some(&other)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let mut seen: Vec<String> = Vec::new();
+        for offset in (0..100).step_by(30) {
+            let (status, body) = app
+                .get(
+                    &format!("/pending/topic/rust?limit=30&offset={offset}"),
+                    Some(&owner),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["total"], 100);
+            let page = body["pending"].as_array().unwrap();
+            assert_eq!(page.len(), 30.min(100 - offset));
+            seen.extend(page.iter().map(|t| t.as_str().unwrap().to_string()));
+        }
+        let expected: Vec<String> = (0..100).map(|i| format!("tag{i:03}")).collect();
+        assert_eq!(seen, expected);
+        let (status, _) = app
+            .get("/pending/topic/rust?sort=recent", Some(&owner))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn approve_all_and_reject_all_resolve_pending() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        let pending: HashSet<String> = (0..10).map(|i| format!("tag{i}")).collect();
+        for name in ["yes", "no"] {
+            app.create_topic(name, &owner, &[]).await;
+            app.edit_topic(name, |topic| topic.pending_tags = pending.clone());
+        }
+
+        let (status, body) = app
+            .call(Method::POST, "/approve-all/tag/yes", Some(&owner), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["topic"]["pending_tags"], json!([]));
+        let changed = body["changed"].as_array().unwrap();
+        assert_eq!(changed.len(), 10);
+        assert_eq!(
+            changed[0],
+            json!({ "tag": "tag0", "action": "approve", "new_count": 1 })
+        );
+        let topic = app.topic("yes");
+        assert_eq!(topic.tags.keys().cloned().collect::<HashSet<_>>(), pending);
+        assert!(topic.tags.values().all(|&count| count == 1));
+
+        let (status, body) = app
+            .call(Method::POST, "/reject-all/tag/no", Some(&owner), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["changed"][9],
+            json!({ "tag": "tag9", "action": "reject" })
+        );
+        let topic = app.topic("no");
+        assert!(topic.pending_tags.is_empty() && topic.tags.is_empty());
+    }
+
+    #[tokio::test]
+    async fn page_size_defaults_and_clamps() {
+        let mut state = test_state();
+        state.page_config = PageConfig {
+            default_size: 2,
+            max_size: 3,
+        };
+        let app = TestApp::new(state);
+        let owner = app.user(1, false);
+        for name in ["a", "b", "c", "d"] {
+            app.create_topic(name, &owner, &[]).await;
+        }
+
+        let (_, body) = app.get("/top", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        let (_, body) = app.get("/top?limit=100", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 3);
+        // offset 越界时返回空数组, 总数不变
+        let response = app
+            .send(Request::get("/top?offset=10").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(response.headers()["x-total-count"], "4");
+        assert_eq!(body_json(response).await, json!([]));
+        for query in ["limit=-1", "offset=-5", "limit=ten"] {
+            let (status, body) = app.get(&format!("/top?{query}"), None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{query}");
+            assert_eq!(body["error"]["kind"], "invalid_pagination");
+        }
+    }
+
+    #[tokio::test]
+    async fn locked_topic_rejects_votes_but_reads() {
+        let app = TestApp::default();
+        let (admin, owner, voter) = (app.user(1, true), app.user(2, false), app.user(3, false));
+        app.create_topic("rust", &owner, &["lang"]).await;
+        let (status, body) = app
+            .call(Method::POST, "/lock/topic/rust", Some(&owner), None)
+            .await;
+        assert_eq!(
+            (status, body["locked"].clone()),
+            (StatusCode::OK, json!(true))
+        );
+
+        let (status, body) = app
+            .post("/add/tag/rust", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+        assert_eq!(status, StatusCode::LOCKED);
+        assert_eq!(body["error"]["kind"], "topic_locked");
+        let (status, body) = app.get("/topic/rust", None).await;
+        assert_eq!(
+            (status, body["tags"]["lang"].clone()),
+            (StatusCode::OK, json!(1))
+        );
+        // 管理员不受锁定限制
+        let (status, _) = app
+            .post("/add/tag/rust", Some(&admin), json!({ "tag": "lang" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        app.call(Method::POST, "/unlock/topic/rust", Some(&owner), None)
+            .await;
+        let (status, _) = app
+            .post("/add/tag/rust", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn raw_user_record_decodes_without_secrets() {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        let app = TestApp::default();
+        let (admin, user) = (app.user(1, true), app.user(2, false));
+        app.state
+            .transaction(|helper| {
+                let mut user_status: UserStatus = helper.get_or_not_found(&2u64)?;
+                user_status.data_mut().access_token = "gho_secret".to_string();
+                helper.insert(&2u64, &user_status)
+            })
+            .unwrap();
+
+        let (status, _) = app
+            .get("/private/admin/raw?prefix=%40&key=2", Some(&user))
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = app
+            .get("/private/admin/raw?prefix=%40&key=2", Some(&admin))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["redacted"], true);
+        let bytes = STANDARD.decode(body["base64"].as_str().unwrap()).unwrap();
+        assert_eq!(body["len"], bytes.len());
+        let user_status: UserStatus = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(user_status.data().login, "user2");
+        assert!(user_status.data().access_token.is_empty());
+        assert!(!body.to_string().contains("gho_secret"));
+
+        let (status, _) = app
+            .get("/private/admin/raw?prefix=%23&key=missing", Some(&admin))
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = app
+            .get("/private/admin/raw?prefix=%3F&key=x", Some(&admin))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn page_config_is_extracted_alone() {
+        // 只取分页配置的 handler, 能编译即说明 FromRef 生效
+        async fn page_sizes(State(config): State<PageConfig>) -> Json<serde_json::Value> {
+            Json(json!([config.default_size, config.max_size]))
+        }
+
+        let mut state = test_state();
+        state.page_config = PageConfig {
+            default_size: 7,
+            max_size: 9,
+        };
+        let router = axum::Router::new()
+            .route("/sizes", get(page_sizes))
+            .with_state(Arc::new(state));
+        let response = router
+            .oneshot(Request::get("/sizes").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(body_json(response).await, json!([7, 9]));
+    }
+
+    #[tokio::test]
+    async fn search_sorts_and_pages_public_matches() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        for name in ["a", "b", "c", "hidden"] {
+            app.create_topic(name, &owner, &[]).await;
+        }
+        app.make_private("hidden", &owner).await;
+        app.edit_topic("a", |topic| {
+            topic.tags.insert("rust".to_string(), 9);
+            topic.updated_at = 1;
+        });
+        app.edit_topic("b", |topic| topic.updated_at = 2);
+        app.edit_topic("c", |topic| {
+            topic.tags.insert("rust".to_string(), 1);
+            topic.updated_at = 3;
+        });
+
+        let (_, body) = app.get("/search?q=about&sort=popular", None).await;
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["topics"], json!(["a", "c", "b"]));
+        let (_, body) = app.get("/search?q=about&sort=recent", None).await;
+        assert_eq!(body["topics"], json!(["c", "b", "a"]));
+        let (_, body) = app
+            .get("/search?q=about&sort=recent&limit=1&offset=1", None)
+            .await;
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["topics"], json!(["b"]));
+
+        let (status, _) = app.get("/search?q=about&sort=oldest", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn empty_topic_names_never_reach_the_store() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("a", &owner, &[]).await;
+
+        let (status, _) = app.get("/topic/", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = app
+            .post("/topic/", Some(&owner), json!({ "description": "x" }))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, body) = app
+            .post("/topic/%20%20", Some(&owner), json!({ "description": "x" }))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+
+        let (_, body) = app.get("/top", None).await;
+        assert_eq!(body, json!(["a"]));
+    }
+
+    #[tokio::test]
+    async fn snapshot_checksum_is_stable() {
+        use sha2::{Digest, Sha256};
+        let nonce: u64 = rand::random();
+        let dir = std::env::temp_dir().join(format!("tagme-snapshots-{nonce:x}"));
+        std::fs::create_dir(&dir).unwrap();
+        let uri = |name: &str| format!("/private/admin/snapshot?name={name}");
+
+        // 没配置 snapshot_dir 时不能导出
+        let app = TestApp::default();
+        let admin = app.user(1, true);
+        let (status, _) = app.call(Method::POST, &uri("a"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let app = TestApp::new(AppState {
+            snapshot_dir: Some(dir.clone()),
+            ..test_state()
+        });
+        let (admin, user) = (app.user(1, true), app.user(2, false));
+        app.create_topic("rust", &user, &["lang"]).await;
+        let (status, _) = app.call(Method::POST, &uri("0"), Some(&user), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(!dir.join("0").exists());
+        // 只接受单个文件名
+        for name in [
+            "",
+            "..",
+            ".hidden",
+            "..%2Fescape",
+            "sub%2Ffile",
+            "%2Ftmp%2Fx",
+        ] {
+            let (status, body) = app.call(Method::POST, &uri(name), Some(&admin), None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{name}");
+            assert_eq!(body["error"]["message"], "Invalid snapshot name", "{name}");
+        }
+
+        let (status, first) = app.call(Method::POST, &uri("1"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::OK, "{first}");
+        let (_, second) = app.call(Method::POST, &uri("2"), Some(&admin), None).await;
+        assert_eq!(first["sha256"], second["sha256"]);
+        let bytes = std::fs::read(dir.join("1")).unwrap();
+        assert_eq!(first["bytes"], bytes.len());
+        let digest: String = Sha256::digest(&bytes)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_eq!(first["sha256"], digest);
+
+        // 每个集合是 (类型, 名称), 若干 Some(记录), 最后一个 None
+        let mut reader = bytes.as_slice();
+        let mut records: usize = 0;
+        while !reader.is_empty() {
+            let _: (Vec<u8>, Vec<u8>) = rmp_serde::decode::from_read(&mut reader).unwrap();
+            let next = |reader: &mut &[u8]| -> Option<Vec<Vec<u8>>> {
+                rmp_serde::decode::from_read(reader).unwrap()
+            };
+            while next(&mut reader).is_some() {
+                records += 1;
+            }
+        }
+        assert!(records >= 3, "{records}");
+
+        // 已存在的文件不覆盖
+        let (status, _) = app.call(Method::POST, &uri("1"), Some(&admin), None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(std::fs::read(dir.join("1")).unwrap(), bytes);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn edited_topic_moves_to_front_of_recent() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        for (i, name) in ["a", "b", "c", "hidden"].into_iter().enumerate() {
+            app.create_topic(name, &owner, &[]).await;
+            app.edit_topic(name, |topic| topic.updated_at = i as i64 + 1);
+        }
+        app.make_private("hidden", &owner).await;
+
+        let (_, body) = app.get("/recent", None).await;
+        let names = |body: &serde_json::Value| -> Vec<String> {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|t| t["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(names(&body), ["c", "b", "a"]);
+        assert_eq!(body[0]["updated_at"], 3);
+        let response = app
+            .send(Request::get("/recent?limit=1").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(response.headers()["x-total-count"], "3");
+
+        app.post("/add/tag/a", Some(&owner), json!({ "tag": "rust" }))
+            .await;
+        let (_, body) = app.get("/recent?limit=2", None).await;
+        assert_eq!(names(&body), ["a", "c"]);
+        assert!(body[0]["updated_at"].as_i64().unwrap() > 3);
+    }
+
+    #[tokio::test]
+    async fn tag_policy_allows_and_denies_suggestions() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("t", &owner, &[]).await;
+        let suggest = |tag: &'static str| {
+            let (app, voter) = (&app, &voter);
+            async move {
+                app.post("/add/tag/t", Some(voter), json!({ "tag": tag }))
+                    .await
+                    .0
+            }
+        };
+
+        let (status, _) = app
+            .post(
+                "/policy/tag/t",
+                Some(&voter),
+                json!({ "denied_tags": ["spam"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // 拒绝列表模式
+        let (status, body) = app
+            .post(
+                "/policy/tag/t",
+                Some(&owner),
+                json!({ "denied_tags": ["spam"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["denied_tags"], json!(["spam"]));
+        assert_eq!(suggest("spam").await, StatusCode::FORBIDDEN);
+        assert_eq!(suggest("rust").await, StatusCode::OK);
+
+        // 允许列表模式
+        app.post(
+            "/policy/tag/t",
+            Some(&owner),
+            json!({ "allowed_tags": ["go"], "denied_tags": [] }),
+        )
+        .await;
+        assert_eq!(suggest("python").await, StatusCode::FORBIDDEN);
+        assert_eq!(suggest("go").await, StatusCode::OK);
+        let topic = app.topic("t");
+        assert!(topic.pending_tags.contains("rust") && topic.pending_tags.contains("go"));
+        assert!(!topic.pending_tags.contains("spam") && !topic.pending_tags.contains("python"));
+    }
+
+    #[tokio::test]
+    async fn top_lists_names_or_details() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        for name in ["a", "b", "hidden"] {
+            app.create_topic(name, &owner, &["rust"]).await;
+        }
+        app.make_private("hidden", &owner).await;
+        app.post("/add/tag/b", Some(&voter), json!({ "tag": "rust" }))
+            .await;
+
+        let (status, body) = app.get("/top", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!(["a", "b"]));
+
+        let (_, body) = app.get("/top?detail=true&offset=1", None).await;
+        assert_eq!(
+            body,
+            json!([{ "name": "b", "description": "about b", "tag_total": 2 }])
+        );
+        let (_, body) = app.get("/top?detail=true&authors=true&limit=1", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["name"], "a");
+        assert_eq!(body[0]["author_login"], "user1");
+    }
+
+    #[tokio::test]
+    async fn integrity_reports_dangling_references() {
+        let app = TestApp::default();
+        let (admin, owner, gone) = (app.user(1, true), app.user(2, false), app.user(3, false));
+        app.create_topic("kept", &owner, &[]).await;
+        app.create_topic("lost", &owner, &[]).await;
+        app.create_topic("orphan", &gone, &[]).await;
+        let (status, body) = app.get("/private/admin/integrity", Some(&admin)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["dangling_user_refs"]["count"], 0);
+        assert_eq!(body["orphaned_topics"]["count"], 0);
+
+        // 绕过 handler 删除记录, 模拟带外删除
+        app.state
+            .transaction(|helper| {
+                helper.remove::<_, Topic>(&"lost".to_string())?;
+                helper.remove::<_, UserStatus>(&3u64)
+            })
+            .unwrap();
+
+        let (status, _) = app.get("/private/admin/integrity", Some(&owner)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (_, body) = app.get("/private/admin/integrity", Some(&admin)).await;
+        assert_eq!(
+            body["dangling_user_refs"],
+            json!({ "count": 1, "sample": [{ "uid": "2", "topic": "lost" }] })
+        );
+        assert_eq!(
+            body["dangling_top_refs"],
+            json!({ "count": 1, "sample": ["lost"] })
+        );
+        assert_eq!(
+            body["orphaned_topics"],
+            json!({ "count": 1, "sample": [{ "topic": "orphan", "author": "3" }] })
+        );
+    }
+
+    #[tokio::test]
+    async fn pretty_query_indents_json() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &["lang"]).await;
+        let text = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let response = app
+                    .send(Request::get(uri).body(Body::empty()).unwrap())
+                    .await;
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+
+        let compact = text("/topic/rust").await;
+        let pretty = text("/topic/rust?pretty=true").await;
+        assert!(!compact.contains('\n'));
+        assert!(pretty.contains("\n  \"description\""));
+        let parse = |s: &str| serde_json::from_str::<serde_json::Value>(s).unwrap();
+        assert_eq!(parse(&compact), parse(&pretty));
+        assert!(!text("/topic/rust?pretty=false").await.contains('\n'));
+    }
+
+    #[tokio::test]
+    async fn revoke_webhook_clears_access_token() {
+        use hmac::{Hmac, Mac};
+        let mut state = test_state();
+        state.github_webhook_secret = "hook secret".to_string();
+        let app = TestApp::new(state);
+        let user = app.user(2, false);
+        app.user(3, false);
+        for uid in [2u64, 3] {
+            app.state
+                .transaction(|helper| {
+                    let mut user_status: UserStatus = helper.get_or_not_found(&uid)?;
+                    user_status.data_mut().access_token = "gho_secret".to_string();
+                    helper.insert(&uid, &user_status)
+                })
+                .unwrap();
+        }
+        let access_token = |uid: u64| {
+            app.state
+                .transaction(|helper| helper.get_or_not_found::<_, UserStatus>(&uid))
+                .unwrap()
+                .data()
+                .access_token
+                .clone()
+        };
+        let body = json!({ "action": "revoked", "sender": { "id": 2 } }).to_string();
+        let sign = |secret: &[u8]| {
+            let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret).unwrap();
+            mac.update(body.as_bytes());
+            let hex: String = mac
+                .finalize()
+                .into_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            format!("sha256={hex}")
+        };
+        let deliver = |signature: String| {
+            let request = Request::post("/oauth/github/webhook")
+                .header("X-GitHub-Event", "github_app_authorization")
+                .header("X-Hub-Signature-256", signature)
+                .body(Body::from(body.clone()))
+                .unwrap();
+            async { app.send(request).await.status() }
+        };
+
+        assert_eq!(deliver(sign(b"wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(access_token(2), "gho_secret");
+
+        assert_eq!(deliver(sign(b"hook secret")).await, StatusCode::NO_CONTENT);
+        assert!(access_token(2).is_empty());
+        assert_eq!(access_token(3), "gho_secret");
+        let (status, _) = app.get("/user", Some(&user)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn public_reads_hit_the_cache() {
+        let mut state = test_state();
+        state.response_cache = ResponseCache::new(std::time::Duration::from_secs(60), 16);
+        let app = TestApp::new(state);
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &["lang"]).await;
+        let get = |uri: &str, token: Option<&Token>| {
+            let mut request = Request::get(uri);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, token.to_string());
+            }
+            app.send(request.body(Body::empty()).unwrap())
+        };
+
+        let miss = get("/stats", None).await;
+        assert_eq!(miss.headers()["X-Cache"], "MISS");
+        let hit = get("/stats", None).await;
+        assert_eq!(hit.headers()["X-Cache"], "HIT");
+        assert_eq!(hit.headers()[header::AGE], "0");
+        let body = body_json(miss).await;
+        assert_eq!(body_json(hit).await, body);
+        assert_eq!(
+            body,
+            json!({ "topic_count": 1, "tag_count": 1, "total_votes": 1, "user_count": 1 })
+        );
+
+        // 黑名单里的参数不进入缓存键
+        let hit = get("/stats?pretty=true&_=123", None).await;
+        assert_eq!(hit.headers()["X-Cache"], "HIT");
+        let miss = get("/top?limit=1", None).await;
+        assert_eq!(miss.headers()["X-Cache"], "MISS");
+        let hit = get("/top?_=1&limit=1", None).await;
+        assert_eq!(hit.headers()["X-Cache"], "HIT");
+        // 其余参数 (如 tag) 都区分缓存
+        app.create_topic("go", &owner, &["compiled"]).await;
+        let tagged = get("/top?tag=compiled", None).await;
+        assert_eq!(tagged.headers()["X-Cache"], "MISS");
+        let all = get("/top", None).await;
+        assert_eq!(all.headers()["X-Cache"], "MISS");
+        assert_eq!(body_json(tagged).await, json!(["go"]));
+        assert_eq!(body_json(all).await, json!(["rust", "go"]));
+
+        let authed = get("/stats", Some(&owner)).await;
+        assert_eq!(authed.status(), StatusCode::OK);
+        assert!(!authed.headers().contains_key("X-Cache"));
+    }
+
+    #[tokio::test]
+    async fn cached_topics_revalidate_and_evict_the_oldest() {
+        let mut state = test_state();
+        state.response_cache = ResponseCache::new(std::time::Duration::from_secs(60), 2);
+        let app = TestApp::new(state);
+        let owner = app.user(1, false);
+        for name in ["rust", "go", "zig"] {
+            app.create_topic(name, &owner, &["lang"]).await;
+        }
+        let get = |uri: &str, etag: Option<&HeaderValue>| {
+            let mut request = Request::get(uri);
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            app.send(request.body(Body::empty()).unwrap())
+        };
+
+        let miss = get("/topic/rust", None).await;
+        assert_eq!(miss.headers()["X-Cache"], "MISS");
+        let etag = miss.headers()[header::ETAG].clone();
+        // 命中缓存时同样按 If-None-Match 返回 304
+        let hit = get("/topic/rust", Some(&etag)).await;
+        assert_eq!(hit.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(hit.headers()["X-Cache"], "HIT");
+        assert_eq!(hit.headers()[header::ETAG], etag);
+        let hit = get("/topic/rust", Some(&HeaderValue::from_static("\"stale\""))).await;
+        assert_eq!(hit.status(), StatusCode::OK);
+        assert_eq!(hit.headers()["X-Cache"], "HIT");
+
+        // 超过上限时丢掉最早的一条
+        get("/topic/go", None).await;
+        get("/topic/zig", None).await;
+        assert_eq!(get("/topic/rust", None).await.headers()["X-Cache"], "MISS");
+        assert_eq!(get("/topic/zig", None).await.headers()["X-Cache"], "HIT");
+    }
+
+    #[tokio::test]
+    async fn topic_url_is_stored_and_cleared() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        let (status, body) = app
+            .post(
+                "/topic/rust",
+                Some(&owner),
+                json!({ "description": "lang", "url": "https://www.rust-lang.org/learn" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (_, body) = app.get("/topic/rust", None).await;
+        assert_eq!(body["url"], "https://www.rust-lang.org/learn");
+
+        for url in ["javascript:alert(1)", "ftp://example.com", "https://"] {
+            let (status, _) = app
+                .post(
+                    "/topic/rust",
+                    Some(&owner),
+                    json!({ "description": "lang", "url": url }),
+                )
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{url}");
+        }
+        assert_eq!(
+            app.topic("rust").url.as_deref(),
+            Some("https://www.rust-lang.org/learn")
+        );
+
+        app.post(
+            "/topic/rust",
+            Some(&owner),
+            json!({ "description": "lang", "url": "" }),
+        )
+        .await;
+        assert!(app.topic("rust").url.is_none());
+    }
+
+    #[tokio::test]
+    async fn detailed_top_carries_each_author() {
+        let app = TestApp::default();
+        let (alice, bob, gone) = (app.user(1, false), app.user(2, false), app.user(3, false));
+        app.create_topic("a1", &alice, &[]).await;
+        app.create_topic("b1", &bob, &[]).await;
+        app.create_topic("a2", &alice, &[]).await;
+        app.create_topic("orphan", &gone, &[]).await;
+        app.state
+            .transaction(|helper| helper.remove::<_, UserStatus>(&3u64))
+            .unwrap();
+
+        let (_, body) = app.get("/top?detail=true&authors=true", None).await;
+        let logins: Vec<(&str, &serde_json::Value)> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| (t["name"].as_str().unwrap(), &t["author_login"]))
+            .collect();
+        assert_eq!(
+            logins,
+            [
+                ("a1", &json!("user1")),
+                ("b1", &json!("user2")),
+                ("a2", &json!("user1")),
+                ("orphan", &json!(null)),
+            ]
+        );
+        assert!(body[3]["author_avatar_url"].is_null());
+
+        // 不带 authors 时不附带作者字段
+        let (_, body) = app.get("/top?detail=true", None).await;
+        assert!(body[0].get("author_login").is_none());
+    }
+
+    #[tokio::test]
+    async fn excess_requests_are_shed() {
+        let slots = Arc::new(tokio::sync::Semaphore::new(2));
+        let mut state = test_state();
+        state.request_slots = Some(slots.clone());
+        let app = TestApp::new(state);
+
+        let (status, _) = app.get("/top", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // 占满所有名额, 模拟正在处理的请求
+        let busy = slots.clone().acquire_many_owned(2).await.unwrap();
+        let response = app
+            .send(Request::get("/top").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        let (status, _) = app.get("/health", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        drop(busy);
+        let (status, _) = app.get("/top", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn stale_version_edit_conflicts() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &[]).await;
+        let (_, body) = app.get("/topic/rust", None).await;
+        assert_eq!(body["version"], 0);
+        let edit = |description: &'static str, version: u64| {
+            let (app, owner) = (&app, &owner);
+            async move {
+                app.post(
+                    "/topic/rust",
+                    Some(owner),
+                    json!({ "description": description, "expected_version": version }),
+                )
+                .await
+            }
+        };
+
+        let (status, body) = edit("first", 0).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["version"], 1);
+        // 基于旧版本的编辑不落库
+        let (status, _) = edit("stale", 0).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(app.topic("rust").description, "first");
+
+        let (status, body) = edit("second", 1).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["version"], 2);
+        assert_eq!(body["description"], "second");
+    }
+
+    #[tokio::test]
+    async fn orphaned_topic_can_be_claimed_and_edited() {
+        let app = TestApp::default();
+        let (author, claimant) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &author, &[]).await;
+
+        let (status, _) = app
+            .call(Method::POST, "/claim/topic/rust", Some(&claimant), None)
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        app.state
+            .transaction(|helper| helper.remove::<_, UserStatus>(&1u64))
+            .unwrap();
+        let (status, body) = app
+            .call(Method::POST, "/claim/topic/rust", Some(&claimant), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["author"], "2");
+        let (_, body) = app.get("/user", Some(&claimant)).await;
+        assert_eq!(body["topics"], json!(["rust"]));
+
+        let (status, _) = app
+            .post(
+                "/topic/rust",
+                Some(&claimant),
+                json!({ "description": "mine" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(app.topic("rust").description, "mine");
+    }
+
+    #[tokio::test]
+    async fn oversized_tag_is_rejected_before_the_handler() {
+        let app = TestApp::default();
+        let long_tag = "x".repeat(65);
+        // 未登录, topic 也不存在: 都还没轮到 handler 检查; 删除只检查非空
+        for (path, tag, kind) in [
+            ("/add/tag/missing", long_tag.as_str(), "tag_too_long"),
+            ("/del/tag/missing", "", "tag_empty"),
+        ] {
+            let (status, body) = app.post(path, None, json!({ "tag": tag })).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{path}");
+            assert_eq!(body["error"]["kind"], kind);
+        }
+        let (status, _) = app
+            .post("/add/tag/missing", None, json!({ "tag": "ok" }))
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn top_filters_by_tag() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &["lang"]).await;
+        app.create_topic("cat", &owner, &["animal"]).await;
+        app.create_topic("go", &owner, &["lang"]).await;
+        app.create_topic("secret", &owner, &["lang"]).await;
+        app.make_private("secret", &owner).await;
+
+        let response = app
+            .send(Request::get("/top?tag=lang").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(response.headers()["X-Total-Count"], "2");
+        assert_eq!(body_json(response).await, json!(["rust", "go"]));
+        let (_, body) = app.get("/top?tag=lang&offset=1", None).await;
+        assert_eq!(body, json!(["go"]));
+        let (status, body) = app.get("/top?tag=unused", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn oauth_login_pins_the_redirect_uri() {
+        let location = |app: TestApp| async move {
+            let response = app
+                .send(Request::get("/oauth/login").body(Body::empty()).unwrap())
+                .await;
+            assert_eq!(response.status(), StatusCode::FOUND);
+            let location = response.headers()[header::LOCATION].to_str().unwrap();
+            reqwest::Url::parse(location).unwrap()
+        };
+
+        let mut state = test_state();
+        state.oauth_client_id = "client".to_string();
+        state.oauth_redirect_uri = "https://tagme.example/oauth/github?x=1".to_string();
+        let url = location(TestApp::new(state)).await;
+        assert_eq!(url.host_str(), Some("github.com"));
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["client_id"], "client");
+        assert_eq!(
+            params["redirect_uri"],
+            "https://tagme.example/oauth/github?x=1"
+        );
+
+        // 未配置时交给 GitHub 使用应用设置里的地址
+        let url = location(TestApp::default()).await;
+        assert!(!url.query_pairs().any(|(k, _)| k == "redirect_uri"));
+
+        let (status, _) = TestApp::default()
+            .get("/oauth/callback?error=access_denied", None)
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn tag_history_lists_newest_first() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &[]).await;
+        app.post("/add/tag/rust", Some(&owner), json!({ "tag": "lang" }))
+            .await;
+        app.post("/add/tag/rust", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+        app.post("/del/tag/rust", Some(&owner), json!({ "tag": "lang" }))
+            .await;
+
+        let (status, _) = app.get("/tag-history/topic/rust", Some(&voter)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = app.get("/tag-history/topic/rust", Some(&owner)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total"], 3);
+        let events: Vec<(&str, &str, &serde_json::Value)> = body["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                (
+                    e["action"].as_str().unwrap(),
+                    e["actor"].as_str().unwrap(),
+                    &e["count_after"],
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ("delete", "1", &json!(null)),
+                ("vote", "2", &json!(2)),
+                ("add", "1", &json!(1)),
+            ]
+        );
+
+        let (_, body) = app
+            .get("/tag-history/topic/rust?limit=1&offset=1", Some(&owner))
+            .await;
+        assert_eq!(body["events"][0]["action"], "vote");
+    }
+
+    #[tokio::test]
+    async fn base_path_prefixes_routes_and_redirects() {
+        let mut state = test_state();
+        state.base_path = "/api".to_string();
+        let state = Arc::new(state);
+        let app = TestApp {
+            router: nest_base_path(router(state.clone(), cors_layer(&[], false, 0)), "/api"),
+            state,
+        };
+        let owner = app.user(1, false);
+        let (status, _) = app
+            .post(
+                "/api/topic/rust",
+                Some(&owner),
+                json!({ "description": "lang" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = app.get("/api/top", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!(["rust"]));
+        let (status, _) = app.get("/top", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let request = Request::delete("/api/topic/rust")
+            .header(header::AUTHORIZATION, owner.to_string())
+            .header(header::ACCEPT, "text/html")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/api/");
+    }
+
+    #[tokio::test]
+    async fn tag_stats_count_topics_and_votes() {
+        let app = TestApp::default();
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &["lang", "fast"]).await;
+        app.create_topic("go", &owner, &["lang"]).await;
+        app.create_topic("secret", &owner, &["lang"]).await;
+        app.make_private("secret", &owner).await;
+        app.post("/add/tag/go", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+
+        let (status, body) = app
+            .post(
+                "/tags/stats",
+                None,
+                json!({ "tags": ["lang", "fast", "unused"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({
+                "lang": { "topics": 2, "total": 3 },
+                "fast": { "topics": 1, "total": 1 },
+                "unused": { "topics": 0, "total": 0 },
+            })
+        );
+
+        let tags: Vec<String> = (0..101).map(|i| format!("t{i}")).collect();
+        let (status, _) = app.post("/tags/stats", None, json!({ "tags": tags })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn malformed_bodies_use_the_error_envelope() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &[]).await;
+        let send = |uri: &'static str, content_type: &'static str, body: &'static str| {
+            let request = Request::post(uri)
+                .header(header::AUTHORIZATION, owner.to_string())
+                .header(header::CONTENT_TYPE, content_type)
+                .body(Body::from(body))
+                .unwrap();
+            let app = &app;
+            async move {
+                let response = app.send(request).await;
+                (response.status(), body_json(response).await)
+            }
+        };
+
+        // ValidatedJson 与普通 JSON body 的 handler 一致
+        for uri in ["/add/tag/rust", "/render"] {
+            let (status, body) = send(uri, "application/json", "{\"tag\": ").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(body["error"]["kind"], "invalid_json");
+            assert_eq!(body["error"]["code"], 400);
+        }
+        let (status, body) = send("/add/tag/rust", "application/json", "{}").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["kind"], "invalid_json");
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("missing field `tag`")
+        );
+        let (status, body) = send("/render", "text/plain", "{}").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["error"]["kind"], "invalid_json");
+    }
+
+    #[tokio::test]
+    async fn embed_token_never_authorizes_writes() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &["lang"]).await;
+        let (_, body) = app
+            .post("/token/embed", Some(&owner), json!({ "topic": "rust" }))
+            .await;
+        let embed = body["token"].as_str().unwrap().to_string();
+
+        for (method, uri, body) in [
+            (
+                Method::POST,
+                "/del/vote/rust",
+                Some(json!({ "tag": "lang" })),
+            ),
+            (
+                Method::POST,
+                "/topic/rust",
+                Some(json!({ "description": "x" })),
+            ),
+            (Method::DELETE, "/topic/rust", None),
+            (Method::POST, "/private/admin?op=1", None),
+        ] {
+            // 作为嵌入 token 和冒充登录 token 都不行
+            for (name, value) in [
+                ("X-Embed-Token", embed.clone()),
+                ("Authorization", format!("Bearer {embed}")),
+            ] {
+                let request = Request::builder()
+                    .method(method.clone())
+                    .uri(uri)
+                    .header(name, &value);
+                let request = match &body {
+                    Some(body) => request
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(body.to_string())),
+                    None => request.body(Body::empty()),
+                };
+                let status = app.send(request.unwrap()).await.status();
+                assert_eq!(status, StatusCode::UNAUTHORIZED, "{method} {uri} {name}");
+            }
+        }
+        let topic = app.topic("rust");
+        assert_eq!(
+            (topic.description.as_str(), topic.tags["lang"]),
+            ("about rust", 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed_or_generated() {
+        let app = TestApp::default();
+        let request_id = |request: Request<Body>| async {
+            let response = app.send(request).await;
+            response.headers()["X-Request-Id"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let given = Request::get("/top")
+            .header("X-Request-Id", "client-chosen-42")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(request_id(given).await, "client-chosen-42");
+
+        let first = request_id(Request::get("/top").body(Body::empty()).unwrap()).await;
+        // 错误响应同样带上
+        let second = request_id(Request::get("/topic/missing").body(Body::empty()).unwrap()).await;
+        assert_eq!((first.len(), second.len()), (32, 32));
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn roles_change_in_bulk_but_never_self_demote() {
+        let app = TestApp::default();
+        let admin = app.user(1, true);
+        let user = app.user(2, false);
+        app.user(3, false);
+        app.user(4, true);
+        let status_of = |uid: u64| {
+            app.state
+                .transaction(|helper| helper.get_or_not_found::<_, UserStatus>(&uid))
+                .unwrap()
+                .is_admin()
+        };
+
+        let (status, _) = app
+            .post(
+                "/private/admin/roles",
+                Some(&user),
+                json!({ "promote": [2] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = app
+            .post(
+                "/private/admin/roles",
+                Some(&admin),
+                json!({ "promote": [2, 3], "demote": [4, 9] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            body,
+            json!({ "2": "promoted", "3": "promoted", "4": "demoted", "9": "not_found" })
+        );
+        assert!(status_of(2) && status_of(3) && !status_of(4));
+
+        // 包含自己时整批拒绝
+        let (status, _) = app
+            .post(
+                "/private/admin/roles",
+                Some(&admin),
+                json!({ "demote": [2, 1] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(status_of(1) && status_of(2));
+    }
+
+    #[tokio::test]
+    async fn avatar_override_replaces_and_falls_back() {
+        let app = TestApp::default();
+        let user = app.user(1, false);
+        app.state
+            .transaction(|helper| {
+                let mut user_status: UserStatus = helper.get_or_not_found(&1u64)?;
+                user_status.data_mut().avatar_url = "https://avatars.github.com/u/1".to_string();
+                helper.insert(&1u64, &user_status)
+            })
+            .unwrap();
+        let patch = |url: &'static str| {
+            let (app, user) = (&app, &user);
+            async move {
+                app.call(
+                    Method::PATCH,
+                    "/user",
+                    Some(user),
+                    Some(json!({ "avatar_override": url })),
+                )
+                .await
+            }
+        };
+
+        let (status, body) = patch("https://example.com/me.png").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["avatar_url"], "https://example.com/me.png");
+        let (_, body) = app.get("/user/1", None).await;
+        assert_eq!(body["avatar_url"], "https://example.com/me.png");
+
+        let (status, body) = patch("javascript:alert(1)").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["fields"][0]["field"], "avatar_override");
+
+        let (_, body) = patch("").await;
+        assert_eq!(body["avatar_url"], "https://avatars.github.com/u/1");
+    }
+
+    #[tokio::test]
+    async fn effective_config_redacts_secrets() {
+        let mut state = test_state();
+        state.effective_config = redact_config(json!({
+            "github_oauth_client_id": "client",
+            "github_oauth_client_secrets": "oauth secret",
+            "github_webhook_secret": "hook secret",
+            "token_secret": "",
+            "read_api_keys": ["read key"],
+            "write_api_keys": { "write key": 7 },
+            "max_page_size": 200,
+        }));
+        let app = TestApp::new(state);
+        let (admin, user) = (app.user(1, true), app.user(2, false));
+
+        let (status, _) = app.get("/private/admin/config", Some(&user)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = app.get("/private/admin/config", Some(&admin)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({
+                "github_oauth_client_id": "client",
+                "github_oauth_client_secrets": "[redacted]",
+                "github_webhook_secret": "[redacted]",
+                "token_secret": "",
+                "read_api_keys": ["[redacted]"],
+                "write_api_keys": [7],
+                "max_page_size": 200,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn young_accounts_get_the_stricter_rate() {
+        let mut state = test_state();
+        state.young_limiter = RateLimiter::new(1);
+        state.new_account_grace_secs = 86400;
+        let app = TestApp::new(state);
+        let (owner, old, young) = (app.user(1, false), app.user(2, false), app.user(3, false));
+        app.state
+            .transaction(|helper| {
+                let mut user_status: UserStatus = helper.get_or_not_found(&3u64)?;
+                user_status.data_mut().created_at = now() - 60;
+                helper.insert(&3u64, &user_status)
+            })
+            .unwrap();
+        app.create_topic("rust", &owner, &[]).await;
+
+        let (status, _) = app
+            .post("/add/tag/rust", Some(&young), json!({ "tag": "a" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = app
+            .post("/add/tag/rust", Some(&young), json!({ "tag": "b" }))
+            .await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["error"]["kind"], "rate_limited");
+        let (status, _) = app
+            .post("/topic/go", Some(&young), json!({ "description": "go" }))
+            .await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        for tag in ["a", "b", "c"] {
+            let (status, _) = app
+                .post("/add/tag/rust", Some(&old), json!({ "tag": tag }))
+                .await;
+            assert_eq!(status, StatusCode::OK, "{tag}");
+        }
+    }
+
+    #[tokio::test]
+    async fn user_topics_detail_follows_the_page() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        for name in ["a", "b", "gone", "c"] {
+            app.create_topic(name, &owner, &["lang"]).await;
+        }
+        // 只删 topic 记录, 留下悬空的名字
+        app.state
+            .transaction(|helper| helper.remove::<_, Topic>(&"gone".to_string()))
+            .unwrap();
+
+        let (_, body) = app.get("/user/1?topics_detail=true", None).await;
+        assert_eq!(
+            body["topics"],
+            json!([
+                { "name": "a", "description": "about a", "tag_total": 1 },
+                { "name": "b", "description": "about b", "tag_total": 1 },
+                { "name": "c", "description": "about c", "tag_total": 1 },
+            ])
+        );
+        let (_, body) = app
+            .get("/user?topics_detail=true&limit=1&offset=1", Some(&owner))
+            .await;
+        assert_eq!(
+            body["topics"],
+            json!([{ "name": "b", "description": "about b", "tag_total": 1 }])
+        );
+        let (_, body) = app.get("/user/1?limit=2", None).await;
+        assert_eq!(body["topics"], json!(["a", "b"]));
+    }
+
+    #[tokio::test]
+    async fn featured_authors_are_listed_until_unfeatured() {
+        let app = TestApp::default();
+        let (admin, user) = (app.user(1, true), app.user(2, false));
+        app.user(3, false);
+        let logins = |body: &serde_json::Value| -> Vec<String> {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|u| u["login"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let feature = |action: &str, uid: u64| format!("/private/admin/{action}/author/{uid}");
+
+        let (status, _) = app
+            .call(Method::POST, &feature("feature", 3), Some(&user), None)
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = app
+            .call(Method::POST, &feature("feature", 9), Some(&admin), None)
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        for uid in [3, 2, 3] {
+            let (status, _) = app
+                .call(Method::POST, &feature("feature", uid), Some(&admin), None)
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (_, body) = app.get("/featured/authors", None).await;
+        assert_eq!(logins(&body), ["user3", "user2"]);
+        assert!(body[0].get("access_token").is_none());
+
+        app.call(Method::POST, &feature("unfeature", 3), Some(&admin), None)
+            .await;
+        let (_, body) = app.get("/featured/authors", None).await;
+        assert_eq!(logins(&body), ["user2"]);
+    }
+
+    #[tokio::test]
+    async fn bulk_visibility_only_touches_own_topics() {
+        let app = TestApp::default();
+        let (owner, other) = (app.user(1, false), app.user(2, false));
+        for name in ["a", "b", "c"] {
+            app.create_topic(name, &owner, &[]).await;
+        }
+        app.create_topic("theirs", &other, &[]).await;
+
+        let (status, body) = app
+            .post(
+                "/me/topics/visibility",
+                Some(&owner),
+                json!({ "names": ["a", "b", "theirs", "missing", "a//b"], "visibility": "private" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({
+                "a": "updated",
+                "b": "updated",
+                "theirs": "skipped",
+                "missing": "not_found",
+                "a//b": "invalid",
+            })
+        );
+        let (_, body) = app.get("/top", None).await;
+        assert_eq!(body, json!(["c", "theirs"]));
+
+        let (_, body) = app
+            .post(
+                "/me/topics/visibility",
+                Some(&owner),
+                json!({ "names": ["a", "c"], "visibility": "public" }),
+            )
+            .await;
+        assert_eq!(body, json!({ "a": "updated", "c": "unchanged" }));
+        let (_, body) = app.get("/top", None).await;
+        assert_eq!(body, json!(["c", "theirs", "a"]));
+    }
+
+    #[tokio::test]
+    async fn new_topics_are_streamed_with_lag_notices() {
+        let mut state = test_state();
+        state.topic_events = tokio::sync::broadcast::channel(2).0;
+        let app = TestApp::new(state);
+        let owner = app.user(1, false);
+        let response = app
+            .send(Request::get("/events/topics").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        let mut frames = response.into_body().into_data_stream();
+        let mut next_frame = async || {
+            let bytes = frames.next().await.unwrap().unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        app.create_topic("a", &owner, &[]).await;
+        let frame = next_frame().await;
+        assert!(frame.starts_with("event: topic\n"), "{frame}");
+        assert!(frame.contains(r#""name":"a","author":"1""#), "{frame}");
+
+        // 之后变为私有不会补发事件; 读得太慢时先收到 lag
+        app.make_private("a", &owner).await;
+        for name in ["b", "c", "d"] {
+            app.create_topic(name, &owner, &[]).await;
+        }
+        assert_eq!(next_frame().await, "event: lag\ndata: 1\n\n");
+        assert!(next_frame().await.contains(r#""name":"c""#));
+        assert!(next_frame().await.contains(r#""name":"d""#));
+    }
+
+    #[tokio::test]
+    async fn csv_import_sums_counts_and_reports_bad_rows() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("t", &owner, &["rust"]).await;
+        let import = |content_type: &'static str, csv: &'static str| {
+            let request = Request::post("/import/tag/t")
+                .header(header::AUTHORIZATION, owner.to_string())
+                .header(header::CONTENT_TYPE, content_type)
+                .body(Body::from(csv))
+                .unwrap();
+            let app = &app;
+            async move {
+                let response = app.send(request).await;
+                (response.status(), body_json(response).await)
+            }
+        };
+
+        let (status, body) = import(
+            "text/csv",
+            "tag,count\nrust,4\n go , 2\n,1\nzig,zero\na,b,c\n",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["imported"], 2);
+        assert_eq!(body["topic"]["tags"]["rust"], 5);
+        assert_eq!(
+            body["changed"],
+            json!([
+                { "tag": "go", "action": "import", "new_count": 2 },
+                { "tag": "rust", "action": "import", "new_count": 5 },
+            ])
+        );
+        assert_eq!(
+            body["skipped"],
+            json!([
+                { "line": 4, "message": "Tag is empty" },
+                { "line": 5, "message": "Invalid count" },
+                { "line": 6, "message": "Malformed row" },
+            ])
+        );
+        let topic = app.topic("t");
+        assert_eq!((topic.tags["rust"], topic.tags["go"]), (5, 2));
+
+        let (status, _) = import("application/json", "rust,1\n").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn duplicate_descriptions_are_grouped() {
+        let app = TestApp::default();
+        let (admin, owner) = (app.user(1, true), app.user(2, false));
+        for (name, description) in [("a", "Same   text"), ("b", "same text"), ("c", "different")] {
+            let (status, _) = app
+                .post(
+                    &format!("/topic/{name}"),
+                    Some(&owner),
+                    json!({ "description": description }),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let (status, _) = app.get("/private/admin/duplicates", Some(&owner)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (_, body) = app.get("/private/admin/duplicates", Some(&admin)).await;
+        assert_eq!(body["total"], 1);
+        assert_eq!(body["groups"][0]["topics"], json!(["a", "b"]));
+
+        app.post(
+            "/topic/b",
+            Some(&owner),
+            json!({ "description": "now unique" }),
+        )
+        .await;
+        let (_, body) = app.get("/private/admin/duplicates", Some(&admin)).await;
+        assert_eq!(body, json!({ "total": 0, "groups": [] }));
+    }
+
+    #[tokio::test]
+    async fn profile_etags_revalidate_per_scope() {
+        let app = TestApp::default();
+        let user = app.user(1, false);
+        let request = |uri: &str, etag: Option<&HeaderValue>| {
+            let mut request = Request::get(uri).header(header::AUTHORIZATION, user.to_string());
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = app.send(request("/user/1", None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+        let response = app.send(request("/user/1", Some(&etag))).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag);
+
+        // /user 与 /user/{uid} 的 ETag 互不通用
+        let response = app.send(request("/user", Some(&etag))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "private");
+        assert_ne!(response.headers()[header::ETAG], etag);
+
+        app.call(
+            Method::PATCH,
+            "/user",
+            Some(&user),
+            Some(json!({ "avatar_override": "https://example.com/a.png" })),
+        )
+        .await;
+        let response = app.send(request("/user/1", Some(&etag))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag);
+    }
+
+    #[tokio::test]
+    async fn author_topics_list_the_others() {
+        let app = TestApp::default();
+        let author = app.user(1, false);
+        for name in ["a", "b", "c"] {
+            app.create_topic(name, &author, &[]).await;
+        }
+        let names = |body: &serde_json::Value| -> Vec<String> {
+            let mut names: Vec<String> = body["topics"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|t| t["name"].as_str().unwrap().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let (_, body) = app.get("/author-topics/topic/a", None).await;
+        assert_eq!(body["total"], 2);
+        assert_eq!(names(&body), ["b", "c"]);
+
+        // 私有 topic 只对作者本人可见
+        app.make_private("c", &author).await;
+        let (_, body) = app.get("/author-topics/topic/a", None).await;
+        assert_eq!(names(&body), ["b"]);
+        let (_, body) = app.get("/author-topics/topic/a", Some(&author)).await;
+        assert_eq!(names(&body), ["b", "c"]);
+
+        app.state
+            .transaction(|helper| helper.remove::<_, UserStatus>(&1u64))
+            .unwrap();
+        let (status, body) = app.get("/author-topics/topic/a", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "total": 0, "topics": [] }));
+    }
+
+    #[tokio::test]
+    async fn anonymous_votes_use_their_own_weight() {
+        let app = TestApp::new(AppState {
+            anonymous_vote_weight: 0,
+            ..test_state()
+        });
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("t", &owner, &["rust"]).await;
+
+        app.edit_topic("t", |t| t.updated_at = 1);
+        let history_len = || {
+            app.state
+                .transaction(|helper| helper.get::<_, TagHistory>(&"t".to_string()))
+                .unwrap()
+                .map_or(0, |h| h.0.len())
+        };
+        let events = history_len();
+
+        // 不计数的匿名投票什么都不写
+        for _ in 0..2 {
+            let (status, body) = app.post("/add/tag/t", None, json!({ "tag": "rust" })).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["changed"]["action"], "none");
+        }
+        let topic = app.topic("t");
+        assert_eq!((topic.tags["rust"], topic.updated_at), (1, 1));
+        assert_eq!(history_len(), events);
+        app.post("/add/tag/t", Some(&voter), json!({ "tag": "rust" }))
+            .await;
+        assert_eq!(app.topic("t").tags["rust"], 2);
+    }
+
+    #[tokio::test]
+    async fn topics_filter_by_creation_range() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        for (name, day) in [("a", 0), ("b", 2), ("c", 5), ("d", 4), ("e", 9)] {
+            app.create_topic(name, &owner, &[]).await;
+            app.edit_topic(name, |t| t.created_at = day * 86400 + 60);
+        }
+        app.make_private("d", &owner).await;
+        let names = |body: &serde_json::Value| -> Vec<String> {
+            body["topics"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|t| t["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let uri = "/topics?since=1970-01-02&until=1970-01-06";
+        let (status, body) = app.get(uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total"], 2);
+        assert_eq!(names(&body), ["b", "c"]);
+        // 作者能看到自己的私有 topic, 按创建时间排序
+        let (_, body) = app.get(uri, Some(&owner)).await;
+        assert_eq!(names(&body), ["b", "d", "c"]);
+        let (_, body) = app.get(&format!("/topics?since={}", 5 * 86400), None).await;
+        assert_eq!(names(&body), ["c", "e"]);
+        let (_, body) = app.get(&format!("{uri}&limit=1&offset=1"), None).await;
+        assert_eq!(body["total"], 2);
+        assert_eq!(names(&body), ["c"]);
+
+        let (status, body) = app
+            .get("/topics?since=1970-01-06&until=1970-01-02", None)
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["fields"][0]["field"], "since");
+    }
+
+    #[tokio::test]
+    async fn refreshed_token_returns_on_the_same_channel() {
+        let app = TestApp::new(AppState {
+            cookie_auth: true,
+            ..test_state()
+        });
+        let user = app.user(1, false);
+        let cookie = format!(
+            "{}={}",
+            tagme::token::COOKIE_NAME,
+            String::try_from(&user).unwrap()
+        );
+
+        let request = Request::get("/user")
+            .header(header::COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(header::AUTHORIZATION));
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.starts_with(tagme::token::COOKIE_NAME));
+        assert!(set_cookie.contains("HttpOnly; Secure; SameSite=Lax"));
+
+        let request = Request::get("/user")
+            .header(header::AUTHORIZATION, user.to_string())
+            .body(Body::empty())
+            .unwrap();
+        let response = app.send(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(header::SET_COOKIE));
+        let refreshed = Token::try_from(response.headers()).unwrap();
+        assert_eq!(refreshed.sub, 1);
+    }
+
+    #[tokio::test]
+    async fn required_tag_applies_only_on_create() {
+        let app = TestApp::new(AppState {
+            require_tag: true,
+            ..test_state()
+        });
+        let owner = app.user(1, false);
+        let (status, body) = app
+            .post("/topic/bare", Some(&owner), json!({ "description": "d" }))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["message"], "At least one tag is required");
+        let (status, _) = app.get("/topic/bare", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        app.create_topic("tagged", &owner, &["rust"]).await;
+        // 编辑不要求带 tag
+        let (status, body) = app
+            .post(
+                "/topic/tagged",
+                Some(&owner),
+                json!({ "description": "new" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["description"], "new");
+    }
+
+    #[tokio::test]
+    async fn cookie_token_is_read_only_when_enabled() {
+        let request = |token: &Token, via_cookie: bool| {
+            let request = Request::get("/user");
+            match via_cookie {
+                true => request.header(
+                    header::COOKIE,
+                    format!(
+                        "theme=dark; {}={}",
+                        tagme::token::COOKIE_NAME,
+                        String::try_from(token).unwrap()
+                    ),
+                ),
+                false => request.header(header::AUTHORIZATION, token.to_string()),
+            }
+            .body(Body::empty())
+            .unwrap()
+        };
+
+        let app = TestApp::default();
+        let user = app.user(1, false);
+        assert_eq!(
+            app.send(request(&user, false)).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            app.send(request(&user, true)).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let app = TestApp::new(AppState {
+            cookie_auth: true,
+            ..test_state()
+        });
+        let user = app.user(1, false);
+        assert_eq!(
+            app.send(request(&user, true)).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            app.send(request(&user, false)).await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_extends_the_expiry() {
+        let app = TestApp::default();
+        let user = app.user(1, false);
+        let max_age = |token: &Token| -> i64 {
+            let cookie = token.to_cookie(&app.state).unwrap();
+            let cookie = cookie.to_str().unwrap();
+            let (_, rest) = cookie.split_once("Max-Age=").unwrap();
+            rest.split(';').next().unwrap().parse().unwrap()
+        };
+        let refresh = |token: &Token| {
+            Request::get("/token/refresh")
+                .header(header::AUTHORIZATION, token.to_string())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // 旧 token 早一秒签发, 换出的新 token 的 exp 更晚
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let response = app.send(refresh(&user)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let fresh = Token::try_from(response.headers()).unwrap();
+        assert_eq!(fresh.sub, 1);
+        assert!(max_age(&fresh) > max_age(&user));
+
+        let (status, _) = app.get("/token/refresh", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        app.state
+            .transaction(|helper| helper.insert(&1u64, &Revoked(now())))
+            .unwrap();
+        let response = app.send(refresh(&user)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn vote_counts_are_bucketed_by_day() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &["lang"]).await;
+        for uid in 2..=4 {
+            let voter = app.user(uid, false);
+            app.post("/add/tag/rust", Some(&voter), json!({ "tag": "lang" }))
+                .await;
+        }
+        // 把三次投票挪到第一天 10:00, 20:00 和第二天 05:00
+        let times = [86400 + 36000, 86400 + 72000, 2 * 86400 + 18000];
+        app.state
+            .transaction(|helper| {
+                let key = "rust".to_string();
+                let mut history: TagHistory = helper.get_or_not_found(&key)?;
+                let votes = history.0.iter_mut().filter(|e| e.action == "vote");
+                votes.zip(times).for_each(|(e, t)| e.timestamp = t);
+                helper.insert(&key, &history)
+            })
+            .unwrap();
+
+        let (status, body) = app
+            .get("/timeseries/topic/rust?tag=lang&bucket=day", Some(&owner))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!([
+                { "bucket_start": 86400, "votes": 2 },
+                { "bucket_start": 2 * 86400, "votes": 1 },
+            ])
+        );
+        let (_, body) = app
+            .get("/timeseries/topic/rust?tag=lang&bucket=hour", Some(&owner))
+            .await;
+        assert_eq!(body.as_array().unwrap().len(), 3);
+        let (_, body) = app
+            .get(
+                "/timeseries/topic/rust?tag=lang&since=1970-01-03",
+                Some(&owner),
+            )
+            .await;
+        assert_eq!(body, json!([{ "bucket_start": 2 * 86400, "votes": 1 }]));
+
+        let (status, _) = app
+            .get("/timeseries/topic/rust?tag=lang&bucket=month", Some(&owner))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let voter = app.user(2, false);
+        let (status, _) = app
+            .get("/timeseries/topic/rust?tag=lang", Some(&voter))
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn cors_wildcard_subdomains_match_by_suffix() {
+        let origins = ["https://*.example.com".to_string(), "null".to_string()];
+        let app = TestApp::with_cors(test_state(), cors_layer(&origins, true, 0));
+        let allowed = async |origin: &str| {
+            let request = Request::get("/health")
+                .header(header::ORIGIN, origin)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.router.clone().oneshot(request).await.unwrap();
+            response
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .is_some_and(|v| v == origin)
+        };
+        assert!(allowed("https://app.example.com").await);
+        assert!(allowed("https://a.b.example.com").await);
+        assert!(allowed("null").await);
+        for origin in [
+            "https://evil.com",
+            "https://example.com",
+            "http://app.example.com",
+            "https://app.example.com.evil.com",
+            "https://evilexample.com",
+        ] {
+            assert!(!allowed(origin).await, "{origin}");
+        }
+
+        for pattern in ["*", "*.com", "https://*", "https://*.*.com", "app.*.com"] {
+            assert!(!is_safe_origin_pattern(pattern), "{pattern}");
+        }
+        let unsafe_layer = std::panic::catch_unwind(|| cors_layer(&["*".to_string()], true, 0));
+        assert!(unsafe_layer.is_err());
+    }
+
+    #[tokio::test]
+    async fn similar_topics_rank_by_tag_overlap() {
+        let app = TestApp::default();
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &["lang", "fast", "safe"])
+            .await;
+        app.create_topic("go", &owner, &["lang", "fast"]).await;
+        app.create_topic("python", &owner, &["lang", "slow"]).await;
+        app.create_topic("cooking", &owner, &["food"]).await;
+        app.create_topic("zig", &owner, &["lang", "fast", "safe"])
+            .await;
+        app.make_private("zig", &owner).await;
+
+        let (status, body) = app.get("/similar/topic/rust", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["topics"][0]["name"], "go");
+        assert!((body["topics"][0]["similarity"].as_f64().unwrap() - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(body["topics"][1]["name"], "python");
+        assert_eq!(body["topics"][1]["similarity"], 0.25);
+
+        let (_, body) = app.get("/similar/topic/rust?limit=1&offset=1", None).await;
+        assert_eq!(body["topics"][0]["name"], "python");
+        let (status, _) = app.get("/similar/topic/zig", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn topic_responses_never_carry_voter_uids() {
+        let app = TestApp::default();
+        let (admin, owner, voter) = (
+            app.user(1, true),
+            app.user(2, false),
+            app.user(424242, false),
+        );
+        app.create_topic("t", &owner, &["lang"]).await;
+        app.create_topic("u", &owner, &["lang"]).await;
+        app.post("/add/tag/t", Some(&voter), json!({ "tag": "lang" }))
+            .await;
+        app.post("/add/tag/t", Some(&voter), json!({ "tag": "new" }))
+            .await;
+        assert!(app.topic("t").voters["lang"].contains(&424242));
+
+        for (uri, token) in [
+            ("/topic/t", &owner),
+            ("/top?detail=true&authors=true", &owner),
+            ("/recent", &owner),
+            ("/topics", &owner),
+            ("/search?q=about", &owner),
+            ("/similar/topic/t", &owner),
+            ("/author-topics/topic/u", &owner),
+            ("/voted/topic/t?tag=lang", &owner),
+            ("/pending/topic/t", &owner),
+            ("/me/export", &owner),
+            ("/private/admin/raw?prefix=%23&key=t", &admin),
+        ] {
+            let request = Request::get(uri)
+                .header(header::AUTHORIZATION, token.to_string())
+                .body(Body::empty())
+                .unwrap();
+            let response = app.send(request).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body = String::from_utf8_lossy(&body);
+            assert!(
+                !body.contains("424242") && !body.contains("voters"),
+                "{uri}: {body}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn every_write_spends_the_rate_budget() {
+        let mut state = test_state();
+        state.user_limiter = RateLimiter::new(3);
+        let app = TestApp::new(state);
+        let owner = app.user(1, false);
+        app.create_topic("t", &owner, &[]).await;
+
+        let policy = json!({ "denied_tags": ["spam"] });
+        for _ in 0..2 {
+            let (status, _) = app
+                .post("/policy/tag/t", Some(&owner), policy.clone())
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, body) = app.post("/policy/tag/t", Some(&owner), policy).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["error"]["kind"], "rate_limited");
+        let (status, _) = app
+            .post(
+                "/move/tag/t",
+                Some(&owner),
+                json!({ "from": "a", "to": "b" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        // 读接口和登出不受影响
+        let (status, _) = app.get("/topic/t", Some(&owner)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.call(Method::POST, "/logout", Some(&owner), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn oauth_callback_requires_the_login_state() {
+        let app = TestApp::default();
+        let response = app
+            .send(Request::get("/oauth/login").body(Body::empty()).unwrap())
+            .await;
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let params: HashMap<String, String> = reqwest::Url::parse(location)
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert!(set_cookie.contains("HttpOnly; Secure; SameSite=Lax"));
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+        let callback = |state: &str, cookie: Option<&str>| {
+            let mut request =
+                Request::get(format!("/oauth/callback?error=access_denied&state={state}"));
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        let message = |response: Response<Body>| async move {
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            body_json(response).await["error"]["message"].clone()
+        };
+
+        let state = &params["state"];
+        // state 有效时才会继续处理 GitHub 回传的结果
+        let response = app.send(callback(state, Some(&cookie))).await;
+        assert!(
+            response.headers()[header::SET_COOKIE]
+                .to_str()
+                .unwrap()
+                .contains("Max-Age=0")
+        );
+        assert_eq!(message(response).await, "Authorization denied");
+
+        // 没有 cookie (从别的浏览器发起), 或 state 被篡改, 都在换取 token 之前拒绝
+        let rejected = [
+            callback(state, None),
+            callback(state, Some("tagme_oauth_state=0000000000000000")),
+            callback(&state[..state.len() - 2], Some(&cookie)),
+            callback("", Some(&cookie)),
+        ];
+        for request in rejected {
+            assert_eq!(
+                message(app.send(request).await).await,
+                "Invalid OAuth state"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn oauth_callback_checks_the_redirect_uri_locally() {
+        let app_for = |redirect_uri: &str| {
+            let mut state = test_state();
+            state.oauth_redirect_uri = redirect_uri.to_string();
+            TestApp::new(state)
+        };
+        let login = async |app: &TestApp| {
+            let response = app
+                .send(Request::get("/oauth/login").body(Body::empty()).unwrap())
+                .await;
+            let location = response.headers()[header::LOCATION].to_str().unwrap();
+            let params: HashMap<String, String> = reqwest::Url::parse(location)
+                .unwrap()
+                .query_pairs()
+                .into_owned()
+                .collect();
+            let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+            let cookie = set_cookie.split(';').next().unwrap().to_string();
+            (params["state"].clone(), cookie)
+        };
+        let callback = async |app: &TestApp, state: &str, cookie: &str| {
+            let response = app
+                .send(
+                    Request::get(format!("/oauth/callback?error=access_denied&state={state}"))
+                        .header(header::COOKIE, cookie)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            body_json(response).await["error"].clone()
+        };
+
+        let old = app_for("https://old.example.com/oauth/callback");
+        let new = app_for("https://tagme.example.com/oauth/callback");
+        let (state, cookie) = login(&old).await;
+
+        // 登录时的 redirect_uri 与当前配置不一致, 在换取 token 之前拒绝
+        let error = callback(&new, &state, &cookie).await;
+        assert_eq!(error["kind"], "redirect_uri_mismatch");
+        assert_eq!(error["message"], "Redirect URI mismatch");
+
+        // 完全一致时才继续处理 GitHub 回传的结果
+        let error = callback(&old, &state, &cookie).await;
+        assert_eq!(error["message"], "Authorization denied");
+        let (state, cookie) = login(&new).await;
+        let error = callback(&new, &state, &cookie).await;
+        assert_eq!(error["message"], "Authorization denied");
+    }
+
+    #[tokio::test]
+    async fn tag_limits_come_from_config() {
+        let mut state = test_state();
+        state.tag_limits = TagLimits {
+            min_chars: 2,
+            max_chars: 4,
+            max_per_topic: 2,
+        };
+        let app = TestApp::new(state);
+        let owner = app.user(1, false);
+        let (status, body) = app
+            .post(
+                "/topic/rust",
+                Some(&owner),
+                json!({ "description": "lang", "tags": ["ab", "cd", "ef"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["fields"][0]["field"], "tags");
+        assert_eq!(body["error"]["fields"][0]["message"], "Too many tags");
+
+        app.create_topic("rust", &owner, &["lang"]).await;
+        let (status, body) = app
+            .post("/add/tag/rust", Some(&owner), json!({ "tag": "fast" }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = app
+            .post("/add/tag/rust", Some(&owner), json!({ "tag": "faster" }))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["kind"], "tag_too_long");
+        let (status, body) = app
+            .post("/add/tag/rust", Some(&owner), json!({ "tag": " x " }))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["kind"], "tag_too_short");
+    }
+
+    #[tokio::test]
+    async fn topic_name_pattern_is_per_app() {
+        let mut state = test_state();
+        state.topic_name_pattern = Some(regex::Regex::new("^[a-z0-9-]+$").unwrap());
+        let strict = TestApp::new(state);
+        let lenient = TestApp::default();
+        let body = json!({ "description": "lang" });
+
+        let (status, body_res) = strict
+            .post(
+                "/topic/My%20Topic",
+                Some(&strict.user(1, false)),
+                body.clone(),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body_res["error"]["fields"][0]["message"],
+            "Topic does not match topic_name_pattern"
+        );
+        let (status, _) = strict
+            .post(
+                "/topic/my-topic",
+                Some(&strict.user(1, false)),
+                body.clone(),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        // 同一进程里的另一个实例不受影响
+        let (status, _) = lenient
+            .post("/topic/My%20Topic", Some(&lenient.user(1, false)), body)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn lenient_decode_applies_to_reads_and_scans() {
+        let strict = TestApp::default();
+        let mut state = test_state();
+        state.lenient_decode = true;
+        let lenient = TestApp::new(state);
+        for app in [&strict, &lenient] {
+            let owner = app.user(1, false);
+            app.create_topic("rust", &owner, &["lang"]).await;
+            app.state
+                .db()
+                .insert(
+                    [Topic::prefix().as_bytes(), b"broken"].concat(),
+                    &[0xc1, 0xff][..],
+                )
+                .unwrap();
+        }
+
+        let (status, _) = strict.get("/topic/broken", None).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        let (status, _) = strict.get("/stats", None).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        // 坏记录当作不存在, 扫描时跳过
+        let (status, _) = lenient.get("/topic/broken", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = lenient.get("/stats", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["topic_count"], 1);
+    }
+
+    #[tokio::test]
+    async fn cookie_writes_need_the_csrf_header() {
+        let app = TestApp::new(AppState {
+            cookie_auth: true,
+            ..test_state()
+        });
+        let admin = app.user(1, true);
+        app.user(2, false);
+        let cookie = format!(
+            "{}={}",
+            tagme::token::COOKIE_NAME,
+            String::try_from(&admin).unwrap()
+        );
+        let request = |method: Method, csrf: bool| {
+            let request = Request::builder()
+                .method(method)
+                .uri("/private/admin?op=2")
+                .header(header::COOKIE, &cookie);
+            match csrf {
+                true => request.header("X-Requested-With", "XMLHttpRequest"),
+                false => request,
+            }
+            .body(Body::empty())
+            .unwrap()
+        };
+
+        // 跨站 GET 只会带上 cookie, 不能改角色
+        let response = app.send(request(Method::GET, false)).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let response = app.send(request(Method::POST, false)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = body_json(response).await;
+        assert_eq!(body["error"]["kind"], "csrf_header_missing");
+        let (_, body) = app.get("/user/2", None).await;
+        assert_ne!(body["status"], "Admin");
+
+        let response = app.send(request(Method::POST, true)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let (_, body) = app.get("/user/2", None).await;
+        assert_eq!(body["status"], "Admin");
+        // 走 Authorization 头的请求不受影响
+        app.user(3, false);
+        let (status, _) = app
+            .call(Method::POST, "/private/admin?op=3", Some(&admin), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn forwarded_for_is_trusted_only_from_proxies() {
+        let mut state = test_state();
+        state.ip_limiter = RateLimiter::new(1);
+        state.trusted_proxies.insert([10, 0, 0, 1].into());
+        let app = TestApp::new(state);
+        let owner = app.user(1, false);
+        app.create_topic("rust", &owner, &["lang"]).await;
+        let vote = |peer: [u8; 4], forwarded: &str| {
+            let peer = std::net::SocketAddr::from((peer, 40000));
+            Request::post("/add/tag/rust")
+                .header("X-Forwarded-For", forwarded)
+                .header(header::CONTENT_TYPE, "application/json")
+                .extension(ConnectInfo(peer))
+                .body(Body::from(r#"{"tag":"lang"}"#))
+                .unwrap()
+        };
+
+        // 直连的客户端换 X-Forwarded-For 也还是同一个桶
+        let direct = [198, 51, 100, 9];
+        let response = app.send(vote(direct, "203.0.113.1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.send(vote(direct, "203.0.113.2")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // 经受信代理时按最后一个非代理的地址计
+        let proxy = [10, 0, 0, 1];
+        let response = app.send(vote(proxy, "203.0.113.1, 10.0.0.1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.send(vote(proxy, "203.0.113.2")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.send(vote(proxy, "198.51.100.7, 203.0.113.2")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn legacy_short_tags_can_still_be_removed() {
+        let mut state = test_state();
+        state.tag_limits.min_chars = 2;
+        let app = TestApp::new(state);
+        let (owner, voter) = (app.user(1, false), app.user(2, false));
+        app.create_topic("rust", &owner, &["lang"]).await;
+        // 模拟提高 min_chars 之前就有的 tag
+        app.edit_topic("rust", |topic| {
+            for (tag, voters) in [("x", vec![1, 2]), ("y", vec![1])] {
+                topic.tags.insert(tag.to_string(), voters.len() as u32);
+                topic
+                    .voters
+                    .insert(tag.to_string(), voters.into_iter().collect());
+            }
+        });
+
+        let (status, body) = app
+            .post("/add/tag/rust", Some(&owner), json!({ "tag": "z" }))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["kind"], "tag_too_short");
+
+        let (status, body) = app
+            .post("/del/vote/rust", Some(&voter), json!({ "tag": "x" }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["changed"]["new_count"], 1);
+        let (status, body) = app
+            .post("/del/tag/rust", Some(&owner), json!({ "tag": "y" }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(app.topic("rust").tags.get("y"), None);
+
+        for uri in ["/del/tag/rust", "/del/vote/rust"] {
+            let (status, body) = app.post(uri, Some(&owner), json!({ "tag": "" })).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(body["error"]["kind"], "tag_empty", "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn private_and_locked_orphans_need_an_admin() {
+        let app = TestApp::default();
+        let author = app.user(1, false);
+        let (claimant, admin) = (app.user(2, false), app.user(3, true));
+        app.create_topic("secret", &author, &[]).await;
+        app.make_private("secret", &author).await;
+        app.create_topic("frozen", &author, &[]).await;
+        app.edit_topic("frozen", |topic| topic.locked = true);
+        app.state
+            .transaction(|helper| helper.remove::<_, UserStatus>(&1u64))
+            .unwrap();
+        let uri = |name: &str| format!("/claim/topic/{name}");
+        let (status, body) = app
+            .call(Method::POST, &uri("secret"), Some(&claimant), None)
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["kind"], "topic_not_found");
+        let (status, body) = app
+            .call(Method::POST, &uri("frozen"), Some(&claimant), None)
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["kind"], "topic_locked");
+        assert_eq!(app.topic("frozen").author, 1);
+
+        for name in ["secret", "frozen"] {
+            let (status, body) = app.call(Method::POST, &uri(name), Some(&admin), None).await;
+            assert_eq!(status, StatusCode::OK, "{name}");
+            assert_eq!(body["author"], "3", "{name}");
+        }
+    }
 }
@@ -1,28 +1,367 @@
 use axum::http::StatusCode;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, OnceLock};
+use tracing::error;
+
+use crate::cache::ResponseCache;
+use crate::error::{AppError, FieldError};
+use crate::pagination::{PageConfig, Pagination};
+use crate::rate::RateLimiter;
+use crate::token::ApiScope;
 
 pub struct AppState {
-    pub db: sled::Db, // @user & #topic, 非自描述（不含存储键）
+    pub db: OnceLock<sled::Db>, // @user & #topic, 非自描述（不含存储键）
     pub oauth_client_id: String,
     pub oauth_client_secrets: String,
+    pub oauth_redirect_uri: String, // 空表示使用 GitHub 应用里登记的地址
+    pub base_path: String,          // 如 /api, 默认为空
+    pub effective_config: serde_json::Value, // 已脱敏
+    pub user_limiter: RateLimiter<u64>,
+    pub young_limiter: RateLimiter<u64>, // 额外限制注册不久的账号
+    pub new_account_grace_secs: i64,
+    pub anonymous_vote_weight: u32, // 匿名投票给已有 tag 加的票数
+    pub require_tag: bool,          // 创建 topic 时必须带至少一个 tag
+    pub cookie_auth: bool,          // 同时接受 HttpOnly cookie 中的 token
+    pub ip_limiter: RateLimiter<IpAddr>,
+    pub trusted_proxies: HashSet<IpAddr>, // 只有来自这些地址的 X-Forwarded-For 才采信
+    pub login_policy: LoginPolicy,
+    pub api_keys: HashMap<String, ApiScope>,
+    pub page_config: PageConfig,
+    pub tag_limits: TagLimits,
+    pub topic_name_pattern: Option<regex::Regex>, // 可选的 topic 名称规则
+    pub lenient_decode: bool,                     // 无法解码的记录当作不存在, 而不是 500
+    pub tag_blocklist: HashSet<String>,           // 已经 fold_tag
+    pub github_webhook_secret: String,            // 空表示不接收 webhook
+    pub snapshot_dir: Option<std::path::PathBuf>, // None 表示不允许导出快照
+    pub response_cache: ResponseCache,
+    pub request_slots: Option<Arc<tokio::sync::Semaphore>>, // None 表示不限并发
+    pub http: reqwest::Client,                              // 共享连接池
+    pub topic_events: tokio::sync::broadcast::Sender<TopicCreated>, // 全站新建 topic, 不含私有
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct Top(pub Vec<String>);
+impl AppState {
+    pub fn is_blocked_tag(&self, tag: &str) -> bool {
+        self.tag_blocklist.contains(&fold_tag(tag))
+    }
 
-impl Default for Top {
-    fn default() -> Self {
-        Top(Vec::new())
+    pub fn is_ready(&self) -> bool {
+        self.db.get().is_some()
+    }
+
+    /// Normalizes a topic name, see `normalize_topic`.
+    pub fn normalize_topic(&self, raw: &str) -> Result<String, FieldError> {
+        normalize_topic(raw, self.topic_name_pattern.as_ref())
+    }
+
+    /// Only call from routes behind `ready_middleware`.
+    pub fn db(&self) -> &sled::Db {
+        self.db.get().expect("database is not ready")
+    }
+
+    /// `with_transaction` on this state's database, honouring `lenient_decode`.
+    pub fn transaction<F, R>(&self, operation: F) -> Result<R, AppError>
+    where
+        F: Fn(DbHelper<'_>) -> Result<R, AppError>,
+    {
+        transaction(self.db(), self.lenient_decode, operation)
+    }
+
+    pub fn get_one<K: ToKey, V: DbType>(&self, key: &K) -> Result<Option<V>, AppError> {
+        get_one(self.db(), key, self.lenient_decode)
     }
+
+    pub fn scan_all<V: DbType>(&self) -> Result<Vec<(Vec<u8>, V)>, AppError> {
+        scan_all(self.db(), self.lenient_decode)
+    }
+
+    pub fn scan_iter<V: DbType>(
+        &self,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, V), AppError>> + use<V> {
+        scan_iter(self.db(), self.lenient_decode)
+    }
+}
+
+/// Who may log in, by GitHub id or login. An empty allowlist allows everyone.
+#[derive(Default)]
+pub struct LoginPolicy {
+    pub allowlist: Vec<String>,
+    pub denylist: Vec<String>,
+}
+
+impl LoginPolicy {
+    pub fn permits(&self, github_id: u64, login: &str) -> bool {
+        let id = github_id.to_string();
+        let matches = |entry: &String| *entry == id || entry.eq_ignore_ascii_case(login);
+        !self.denylist.iter().any(matches)
+            && (self.allowlist.is_empty() || self.allowlist.iter().any(matches))
+    }
+}
+
+/// Stored under the reserved empty key (`!top`), as is `Motd`. Topics live
+/// under their own `#` prefix and `normalize_topic` never yields an empty
+/// name, so no topic can collide with these singletons.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Top(pub Vec<String>);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MotdLevel {
+    #[default]
+    Info,
+    Warn,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct Motd {
+    pub message: String,
+    #[serde(default)]
+    pub level: MotdLevel,
+}
+
+// GET /events/topics 推送的事件, 在事务提交后发送
+#[derive(Serialize, Clone)]
+pub struct TopicCreated {
+    pub name: String,
+    pub author: String,
+    pub created_at: i64,
+}
+
+// 首页推荐作者, 按推荐顺序; 同 Top 一样存在 !featauthor 的空 key 下
+#[derive(Serialize, Deserialize, Default)]
+pub struct FeaturedAuthors(pub Vec<u64>);
+
+// 吊销记录: !rev<uid> -> 吊销时间 (秒), 在此之前或同一秒内签发的 token 一律无效;
+// 之后登录签发的 token 的 iat 至少是吊销时间 + 1, 见 Token::issue
+#[derive(Serialize, Deserialize)]
+pub struct Revoked(pub i64);
+
+// 全局同义词: !syn<alias> -> canonical tag
+#[derive(Serialize, Deserialize)]
+pub struct TagSynonym(pub String);
+
 #[derive(Serialize, Deserialize)]
 pub struct Topic {
     pub author: u64,
     pub description: String,
     pub tags: HashMap<String, u32>,
     pub pending_tags: HashSet<String>,
+    #[serde(default)]
+    pub voters: HashMap<String, HashSet<u64>>, // 登录用户的投票记录, 只存储, 不出现在任何响应里
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64, // 描述或 tag 的最后修改时间
+    #[serde(default)]
+    pub locked: bool, // 锁定后只有管理员能改 tag
+    #[serde(default)]
+    pub allowed_tags: HashSet<String>, // 空表示不限制
+    #[serde(default)]
+    pub denied_tags: HashSet<String>,
+    #[serde(default)]
+    pub url: Option<String>, // 外部链接, 仅 http(s)
+    #[serde(default)]
+    pub version: u64, // 每次编辑描述加一
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+// 私有 topic 不在 top 列表中, 也不出现在搜索, 统计和他人看到的主页里
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Private,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TagEvent {
+    pub tag: String,
+    pub action: String, // vote / unvote / add / suggest / delete / approve / reject / import
+    pub actor: Option<u64>,
+    pub count_after: Option<u32>,
+    pub timestamp: i64,
+}
+
+// 每个 topic 的 tag 变更记录, 旧的在前, 只保留最近 MAX 条
+#[derive(Serialize, Deserialize, Default)]
+pub struct TagHistory(pub Vec<TagEvent>);
+
+impl TagHistory {
+    const MAX: usize = 200;
+
+    pub fn append(helper: &DbHelper<'_>, topic: &String, event: TagEvent) -> Result<(), AppError> {
+        let mut history: TagHistory = helper.get(topic)?.unwrap_or_default();
+        history.0.push(event);
+        let excess = history.0.len().saturating_sub(Self::MAX);
+        history.0.drain(..excess);
+        helper.insert(topic, &history)
+    }
+}
+
+impl Topic {
+    /// Strong ETag over the stored record, changes on any modification.
+    pub fn etag(&self) -> String {
+        use sha2::{Digest, Sha256};
+        // HashMap/HashSet 每次反序列化后的遍历顺序都不同, 排序后再哈希;
+        // Topic 里没有有序的列表, 所以数组也可以排序
+        fn canonicalize(value: &mut serde_json::Value) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    map.sort_keys();
+                    map.values_mut().for_each(canonicalize);
+                }
+                serde_json::Value::Array(items) => {
+                    items.iter_mut().for_each(canonicalize);
+                    items.sort_by_cached_key(|item| item.to_string());
+                }
+                _ => {}
+            }
+        }
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        canonicalize(&mut value);
+        let digest = Sha256::digest(value.to_string());
+        let hex: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+        format!("\"{hex}\"")
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.visibility == Visibility::Private
+    }
+
+    /// Adds `tag` with one vote from the author, recorded so the author cannot vote again.
+    pub fn seed_tag(&mut self, tag: String) {
+        self.voters
+            .entry(tag.clone())
+            .or_default()
+            .insert(self.author);
+        self.tags.entry(tag).or_insert(1);
+    }
+
+    /// Whether others may suggest `tag` under the owner's allow/deny lists.
+    pub fn permits_tag(&self, tag: &str) -> bool {
+        !self.denied_tags.contains(tag)
+            && (self.allowed_tags.is_empty() || self.allowed_tags.contains(tag))
+    }
+}
+
+pub fn now() -> i64 {
+    time::UtcDateTime::now().unix_timestamp()
+}
+
+/// Normalizes a `{*topic}` path capture into a storage key.
+///
+/// The path is already percent-decoded by axum, so `c%23` arrives as `c#`
+/// (a literal `#` never reaches the server). Slashes are allowed as
+/// separators, but empty, `.` and `..` segments are rejected, as is a name
+/// that trims to nothing. `/topic/` itself never reaches a handler: the
+/// wildcard needs at least one character, so it falls through to `400`.
+/// When a `pattern` is given (`topic_name_pattern` in config) the whole
+/// name must match it.
+pub fn normalize_topic(raw: &str, pattern: Option<&regex::Regex>) -> Result<String, FieldError> {
+    let topic = raw.trim();
+    if topic.len() > 128 {
+        return Err(FieldError::new(
+            "topic",
+            "topic_too_long",
+            "Topic is too long",
+        ));
+    }
+    if topic.is_empty()
+        || topic.chars().any(char::is_control)
+        || topic.split('/').any(|seg| matches!(seg, "" | "." | ".."))
+    {
+        return Err(FieldError::new(
+            "topic",
+            "topic_invalid",
+            "Topic is invalid",
+        ));
+    }
+    if pattern.is_some_and(|pattern| !pattern.is_match(topic)) {
+        return Err(FieldError::new(
+            "topic",
+            "topic_pattern_mismatch",
+            "Topic does not match topic_name_pattern",
+        ));
+    }
+    Ok(topic.to_string())
+}
+
+pub const MAX_DESCRIPTION_CHARS: usize = 4096;
+
+pub fn validate_description(description: &str) -> Result<(), FieldError> {
+    match description.chars().count() > MAX_DESCRIPTION_CHARS {
+        true => Err(FieldError::new(
+            "description",
+            "description_too_long",
+            "Description is too long",
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Tag limits from config, checked when a request body is validated.
+#[derive(Clone, Copy)]
+pub struct TagLimits {
+    pub min_chars: usize, // 默认 1 即只拒绝空 tag, 不含首尾空白
+    pub max_chars: usize,
+    pub max_per_topic: usize, // 创建 topic 时一次最多带的 tag 数
+}
+
+impl Default for TagLimits {
+    fn default() -> Self {
+        Self {
+            min_chars: 1,
+            max_chars: 64,
+            max_per_topic: 32,
+        }
+    }
+}
+
+impl TagLimits {
+    pub fn validate_tag(&self, tag: &str) -> Result<(), FieldError> {
+        check_tag_len(tag, self.min_chars, self.max_chars)
+    }
+}
+
+// 长度都按字符计, 中文 tag 和英文 tag 的上限相同
+fn check_tag_len(tag: &str, min_len: usize, max_len: usize) -> Result<(), FieldError> {
+    if tag.is_empty() {
+        return Err(FieldError::new("tag", "tag_empty", "Tag is empty"));
+    }
+    if tag.trim().chars().count() < min_len {
+        return Err(FieldError::new("tag", "tag_too_short", "Tag is too short"));
+    }
+    match tag.chars().count() > max_len {
+        true => Err(FieldError::new("tag", "tag_too_long", "Tag is too long")),
+        false => Ok(()),
+    }
+}
+
+/// Only absolute `http(s)` URLs, so clients can't be handed `javascript:` links.
+pub fn validate_url(url: &str) -> Result<(), FieldError> {
+    let lower = url.to_ascii_lowercase();
+    let host = lower
+        .strip_prefix("https://")
+        .or_else(|| lower.strip_prefix("http://"))
+        .and_then(|rest| rest.split(['/', '?', '#']).next());
+    match url.len() <= 2048
+        && host.is_some_and(|h| !h.is_empty())
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        true => Ok(()),
+        false => Err(FieldError::new("url", "url_invalid", "URL is invalid")),
+    }
+}
+
+/// Case- and whitespace-insensitive form used for blocklist matching.
+pub fn fold_tag(tag: &str) -> String {
+    tag.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect()
 }
 
 #[derive(Serialize, Deserialize)]
@@ -34,12 +373,24 @@ pub struct UserData {
     pub name: String,
     pub avatar_url: String,
     pub bio: String,
+    #[serde(default)]
+    pub avatar_override: Option<String>, // 优先于 GitHub 头像显示
+    #[serde(default)]
+    pub created_at: i64, // 旧账号为 0
+}
+
+impl UserData {
+    pub fn display_avatar(&self) -> &str {
+        self.avatar_override.as_deref().unwrap_or(&self.avatar_url)
+    }
 }
 
 #[derive(Serialize)]
 pub struct UserInfo {
     id: String,
     topics: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topics_total: Option<usize>, // 分页前的数量, 只在分页后给出
     login: String,
     name: String,
     avatar_url: String,
@@ -47,6 +398,18 @@ pub struct UserInfo {
     status: Option<&'static str>,
 }
 
+impl UserInfo {
+    pub fn page_topics(mut self, page: &Pagination) -> Self {
+        self.topics_total = Some(self.topics.len());
+        self.topics = page.apply(self.topics);
+        self
+    }
+
+    pub fn topics(&self) -> &[String] {
+        &self.topics
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum UserStatus {
     Normal(UserData),
@@ -63,6 +426,8 @@ impl Default for UserStatus {
             name: String::new(),
             avatar_url: String::new(),
             bio: String::new(),
+            avatar_override: None,
+            created_at: now(),
         })
     }
 }
@@ -77,9 +442,10 @@ impl UserStatus {
         UserInfo {
             id: uid.to_string(),
             topics: user.topics,
+            topics_total: None,
             login: user.login,
             name: user.name,
-            avatar_url: user.avatar_url,
+            avatar_url: user.avatar_override.unwrap_or(user.avatar_url),
             bio: user.bio,
             status,
         }
@@ -111,44 +477,54 @@ impl UserStatus {
         matches!(self, Self::Banned(_))
     }
 
-    pub fn as_active(&self) -> Result<(), (StatusCode, &'static str)> {
+    pub fn as_admin(&self) -> Result<(), AppError> {
+        match self.is_admin() {
+            true => Ok(()),
+            false => Err((StatusCode::FORBIDDEN, "admin_required", "Admin required").into()),
+        }
+    }
+
+    pub fn as_active(&self) -> Result<(), AppError> {
         match self.is_banned() {
             true => Err((
                 StatusCode::FORBIDDEN,
+                "user_banned",
                 "Attempted to request an invalid user",
-            )),
+            )
+                .into()),
             false => Ok(()),
         }
     }
 
-    pub fn active_data(&self) -> Result<&UserData, (StatusCode, &'static str)> {
+    pub fn active_data(&self) -> Result<&UserData, AppError> {
         self.as_active()?;
         Ok(self.data())
     }
 
-    pub fn active_data_mut(&mut self) -> Result<&mut UserData, (StatusCode, &'static str)> {
+    pub fn active_data_mut(&mut self) -> Result<&mut UserData, AppError> {
         self.as_active()?;
         Ok(self.data_mut())
     }
 
-    pub fn into_active_data(self) -> Result<UserData, (StatusCode, &'static str)> {
+    pub fn into_active_data(self) -> Result<UserData, AppError> {
         self.as_active()?;
         Ok(self.into_data())
     }
 
-    pub fn as_authorized(&self, uid: u64, author: u64) -> Result<(), (StatusCode, &'static str)> {
+    pub fn as_authorized(&self, uid: u64, author: u64) -> Result<(), AppError> {
         match uid == author || self.is_admin() {
             true => Ok(()),
-            false => Err((StatusCode::FORBIDDEN, "Access to the resource is denied")),
+            false => Err((
+                StatusCode::FORBIDDEN,
+                "not_owner",
+                "Access to the resource is denied",
+            )
+                .into()),
         }
     }
 
     #[deprecated = "Use verified_data instead"]
-    pub fn authorized_data(
-        &self,
-        uid: u64,
-        author: u64,
-    ) -> Result<&UserData, (StatusCode, &'static str)> {
+    pub fn authorized_data(&self, uid: u64, author: u64) -> Result<&UserData, AppError> {
         self.as_authorized(uid, author)?;
         Ok(self.data())
     }
@@ -158,138 +534,291 @@ impl UserStatus {
         &mut self,
         uid: u64,
         author: u64,
-    ) -> Result<&mut UserData, (StatusCode, &'static str)> {
+    ) -> Result<&mut UserData, AppError> {
         self.as_authorized(uid, author)?;
         Ok(self.data_mut())
     }
 
     #[deprecated = "Use into_verified_data instead"]
-    pub fn into_authorized_data(
-        self,
-        uid: u64,
-        author: u64,
-    ) -> Result<UserData, (StatusCode, &'static str)> {
+    pub fn into_authorized_data(self, uid: u64, author: u64) -> Result<UserData, AppError> {
         self.as_authorized(uid, author)?;
         Ok(self.into_data())
     }
 
-    pub fn as_verified(&self, uid: u64, author: u64) -> Result<(), (StatusCode, &'static str)> {
+    pub fn as_verified(&self, uid: u64, author: u64) -> Result<(), AppError> {
         self.as_active()?;
         self.as_authorized(uid, author)
     }
 
-    pub fn verified_data(
-        &self,
-        uid: u64,
-        author: u64,
-    ) -> Result<&UserData, (StatusCode, &'static str)> {
+    pub fn verified_data(&self, uid: u64, author: u64) -> Result<&UserData, AppError> {
         self.as_verified(uid, author)?;
         Ok(self.data())
     }
 
-    pub fn verified_data_mut(
-        &mut self,
-        uid: u64,
-        author: u64,
-    ) -> Result<&mut UserData, (StatusCode, &'static str)> {
+    pub fn verified_data_mut(&mut self, uid: u64, author: u64) -> Result<&mut UserData, AppError> {
         self.as_verified(uid, author)?;
         Ok(self.data_mut())
     }
 
-    pub fn into_verified_data(
-        self,
-        uid: u64,
-        author: u64,
-    ) -> Result<UserData, (StatusCode, &'static str)> {
+    pub fn into_verified_data(self, uid: u64, author: u64) -> Result<UserData, AppError> {
         self.as_verified(uid, author)?;
         Ok(self.into_data())
     }
 }
 
-pub struct DbHelper<'a>(&'a sled::transaction::TransactionalTree);
+// 无法解码的记录总会记日志; lenient 时当作不存在, 否则 500
+fn decode_record<V: DbType>(
+    key: &[u8],
+    bytes: &[u8],
+    lenient: bool,
+) -> Result<Option<V>, AppError> {
+    match rmp_serde::from_slice(bytes) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => {
+            error!(
+                "failed to decode {} record {:?} ({} bytes): {e}",
+                V::prefix(),
+                String::from_utf8_lossy(key),
+                bytes.len()
+            );
+            match lenient {
+                true => Ok(None),
+                false => Err((StatusCode::INTERNAL_SERVER_ERROR, "Deserialize failed").into()),
+            }
+        }
+    }
+}
+
+pub struct DbHelper<'a> {
+    tree: &'a sled::transaction::TransactionalTree,
+    lenient: bool,
+}
 
 impl<'a> DbHelper<'a> {
-    pub fn new(tree: &'a sled::transaction::TransactionalTree) -> Self {
-        Self(tree)
+    pub fn new(tree: &'a sled::transaction::TransactionalTree, lenient: bool) -> Self {
+        Self { tree, lenient }
     }
 
-    pub fn get<K: ToKey, V: DbType>(
-        &self,
-        key: &K,
-    ) -> Result<Option<V>, (StatusCode, &'static str)> {
-        let prefixed_key = [V::prefix().as_bytes(), &key.to_key()].concat();
-        self.0
-            .get(prefixed_key)
+    pub fn get<K: ToKey, V: DbType>(&self, key: &K) -> Result<Option<V>, AppError> {
+        let key = key.to_key();
+        let prefixed_key = [V::prefix().as_bytes(), &key].concat();
+        let Some(bytes) = self
+            .tree
+            .get(&prefixed_key)
             .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Fetch data failed"))?
-            .map(|bytes| {
-                rmp_serde::from_slice(&bytes)
-                    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Deserialize failed"))
-            })
-            .transpose()
+        else {
+            return Ok(None);
+        };
+        decode_record(&key, &bytes, self.lenient)
     }
 
-    pub fn get_or_not_found<K: ToKey, V: DbType>(
-        &self,
-        key: &K,
-    ) -> Result<V, (StatusCode, &'static str)> {
-        self.get(key)?.ok_or((StatusCode::NOT_FOUND, "Not found"))
+    /// Undecoded bytes under an arbitrary prefix, for debugging.
+    pub fn get_raw(&self, prefix: &str, key: &[u8]) -> Result<Option<Vec<u8>>, AppError> {
+        let prefixed_key = [prefix.as_bytes(), key].concat();
+        Ok(self
+            .tree
+            .get(&prefixed_key)
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Fetch data failed"))?
+            .map(|bytes| bytes.to_vec()))
     }
 
-    pub fn insert<K: ToKey, V: DbType>(
+    pub fn get_many<K: ToKey, V: DbType>(&self, keys: &[K]) -> Result<Vec<Option<V>>, AppError> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    pub fn get_or_not_found<K: ToKey + std::fmt::Display, V: DbType>(
         &self,
         key: &K,
-        value: &V,
-    ) -> Result<(), (StatusCode, &'static str)> {
+    ) -> Result<V, AppError> {
+        self.get(key)?
+            .ok_or_else(|| AppError::not_found_named(V::name(), key))
+    }
+
+    pub fn insert<K: ToKey, V: DbType>(&self, key: &K, value: &V) -> Result<(), AppError> {
         let prefixed_key = [V::prefix().as_bytes(), &key.to_key()].concat();
         let bytes = rmp_serde::to_vec(value)
             .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Serialize failed"))?;
-        self.0
+        self.tree
             .insert(prefixed_key, bytes)
             .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Insert data failed"))?;
         Ok(())
     }
 
-    pub fn remove<K: ToKey, V: DbType>(&self, key: &K) -> Result<(), (StatusCode, &'static str)> {
+    pub fn remove<K: ToKey, V: DbType>(&self, key: &K) -> Result<(), AppError> {
         let prefixed_key = [V::prefix().as_bytes(), &key.to_key()].concat();
-        self.0
+        self.tree
             .remove(prefixed_key)
             .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Remove data failed"))?;
         Ok(())
     }
 }
 
-pub fn with_transaction<F, R>(db: &sled::Db, operation: F) -> Result<R, (StatusCode, &'static str)>
+pub fn canonical_tag(helper: &DbHelper<'_>, tag: &str) -> Result<String, AppError> {
+    let mut tag = tag.to_string();
+    // 写入时已拒绝环, 这里只是兜底
+    for _ in 0..16 {
+        match helper.get::<_, TagSynonym>(&tag)? {
+            Some(TagSynonym(next)) => tag = next,
+            None => break,
+        }
+    }
+    Ok(tag)
+}
+
+/// A single record of type `V`, outside any transaction.
+pub fn get_one<K: ToKey, V: DbType>(
+    db: &sled::Db,
+    key: &K,
+    lenient: bool,
+) -> Result<Option<V>, AppError> {
+    let key = key.to_key();
+    let prefixed_key = [V::prefix().as_bytes(), &key].concat();
+    match db
+        .get(prefixed_key)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Fetch data failed"))?
+    {
+        Some(bytes) => decode_record(&key, &bytes, lenient),
+        None => Ok(None),
+    }
+}
+
+/// Every record of type `V` with its key (prefix stripped), outside any
+/// transaction; when `lenient`, undecodable records are skipped.
+pub fn scan_all<V: DbType>(db: &sled::Db, lenient: bool) -> Result<Vec<(Vec<u8>, V)>, AppError> {
+    scan_iter(db, lenient).collect()
+}
+
+/// Like `scan_all`, but decodes one record at a time.
+pub fn scan_iter<V: DbType>(
+    db: &sled::Db,
+    lenient: bool,
+) -> impl Iterator<Item = Result<(Vec<u8>, V), AppError>> + use<V> {
+    db.scan_prefix(V::prefix()).filter_map(move |entry| {
+        let (key, bytes) = match entry {
+            Ok(entry) => entry,
+            Err(_) => {
+                return Some(Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Fetch data failed",
+                )
+                    .into()));
+            }
+        };
+        let key = key[V::prefix().len()..].to_vec();
+        decode_record(&key, &bytes, lenient)
+            .transpose()
+            .map(|value| value.map(|value| (key, value)))
+    })
+}
+
+/// Runs `operation` in a transaction with strict decoding; handlers go
+/// through `AppState::transaction` so `lenient_decode` applies.
+pub fn with_transaction<F, R>(db: &sled::Db, operation: F) -> Result<R, AppError>
+where
+    F: Fn(DbHelper<'_>) -> Result<R, AppError>,
+{
+    transaction(db, false, operation)
+}
+
+fn transaction<F, R>(db: &sled::Db, lenient: bool, operation: F) -> Result<R, AppError>
 where
-    F: Fn(DbHelper<'_>) -> Result<R, (StatusCode, &'static str)>,
+    F: Fn(DbHelper<'_>) -> Result<R, AppError>,
 {
     use sled::transaction::ConflictableTransactionError as CTError;
-    db.transaction(|tx| operation(DbHelper::new(tx)).map_err(|e| CTError::Abort(e)))
+    db.transaction(|tx| operation(DbHelper::new(tx, lenient)).map_err(CTError::Abort))
         .map_err(|e| match e {
             sled::transaction::TransactionError::Abort(e) => e,
-            _ => (StatusCode::CONFLICT, "Transaction conflict"),
+            _ => AppError::from((
+                StatusCode::CONFLICT,
+                "transaction_conflict",
+                "Transaction conflict",
+            )),
         })
 }
 
 pub trait DbType: Serialize + DeserializeOwned {
     fn prefix() -> &'static str;
+    fn name() -> &'static str;
 }
 
 impl DbType for UserStatus {
     fn prefix() -> &'static str {
         "@"
     }
+
+    fn name() -> &'static str {
+        "User"
+    }
 }
 
 impl DbType for Topic {
     fn prefix() -> &'static str {
         "#"
     }
+
+    fn name() -> &'static str {
+        "Topic"
+    }
+}
+
+impl DbType for TagSynonym {
+    fn prefix() -> &'static str {
+        "!syn"
+    }
+
+    fn name() -> &'static str {
+        "Synonym"
+    }
+}
+
+impl DbType for Motd {
+    fn prefix() -> &'static str {
+        "!motd"
+    }
+
+    fn name() -> &'static str {
+        "Motd"
+    }
+}
+
+impl DbType for TagHistory {
+    fn prefix() -> &'static str {
+        "!taghist"
+    }
+
+    fn name() -> &'static str {
+        "Tag history"
+    }
 }
 
 impl DbType for Top {
     fn prefix() -> &'static str {
         "!top"
     }
+
+    fn name() -> &'static str {
+        "Top"
+    }
+}
+
+impl DbType for Revoked {
+    fn prefix() -> &'static str {
+        "!rev"
+    }
+
+    fn name() -> &'static str {
+        "Revocation"
+    }
+}
+
+impl DbType for FeaturedAuthors {
+    fn prefix() -> &'static str {
+        "!featauthor"
+    }
+
+    fn name() -> &'static str {
+        "Featured authors"
+    }
 }
 
 pub trait ToKey {
@@ -313,3 +842,108 @@ impl ToKey for u64 {
         self.to_le_bytes().to_vec()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_length_counts_chars() {
+        assert_eq!(
+            check_tag_len("x", 2, 64).unwrap_err().message,
+            "Tag is too short"
+        );
+        assert_eq!(
+            check_tag_len(" x ", 2, 64).unwrap_err().message,
+            "Tag is too short"
+        );
+        assert!(check_tag_len("cx", 2, 64).is_ok());
+        assert!(check_tag_len("x", 1, 64).is_ok());
+        assert!(check_tag_len(&"标".repeat(64), 1, 64).is_ok());
+        assert_eq!(
+            check_tag_len(&"标".repeat(65), 1, 64).unwrap_err().message,
+            "Tag is too long"
+        );
+    }
+
+    #[test]
+    fn undecodable_record_is_500_or_missing() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let key = "broken".to_string();
+        db.insert(
+            [Topic::prefix().as_bytes(), key.as_bytes()].concat(),
+            &[0xc1, 0xff][..],
+        )
+        .unwrap();
+
+        let strict = with_transaction(&db, |helper| helper.get::<_, Topic>(&key));
+        assert!(strict.is_err_and(|e| e.status() == StatusCode::INTERNAL_SERVER_ERROR));
+        let lenient = transaction(&db, true, |helper| helper.get::<_, Topic>(&key));
+        assert!(lenient.unwrap().is_none());
+
+        // 事务外的读取和扫描同样遵守 lenient
+        assert!(get_one::<_, Topic>(&db, &key, false).is_err());
+        assert!(get_one::<_, Topic>(&db, &key, true).unwrap().is_none());
+        assert!(scan_all::<Topic>(&db, false).is_err());
+        assert!(scan_all::<Topic>(&db, true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn login_policy_allow_and_deny() {
+        let policy = LoginPolicy::default();
+        assert!(policy.permits(1, "anyone"));
+
+        let policy = LoginPolicy {
+            allowlist: vec!["1".to_string(), "Alice".to_string()],
+            denylist: vec!["2".to_string()],
+        };
+        assert!(policy.permits(1, "someone"));
+        assert!(policy.permits(3, "alice"));
+        assert!(!policy.permits(4, "bob"));
+        assert!(!policy.permits(2, "alice"));
+    }
+
+    #[test]
+    fn etag_is_stable_across_reads() {
+        let mut topic: Topic = rmp_serde::from_slice(
+            &rmp_serde::to_vec(&serde_json::json!({
+                "author": 1, "description": "d", "tags": {}, "pending_tags": []
+            }))
+            .unwrap(),
+        )
+        .unwrap();
+        for i in 0..32 {
+            topic.seed_tag(format!("tag{i}"));
+            topic.voters.get_mut("tag0").unwrap().insert(i);
+        }
+        let bytes = rmp_serde::to_vec(&topic).unwrap();
+        let etags: HashSet<String> = (0..8)
+            .map(|_| rmp_serde::from_slice::<Topic>(&bytes).unwrap().etag())
+            .collect();
+        assert_eq!(etags.len(), 1);
+
+        topic.tags.insert("tag0".to_string(), 2);
+        assert!(!etags.contains(&topic.etag()));
+    }
+
+    #[test]
+    fn topic_name_pattern_is_applied() {
+        let slug = regex::Regex::new("^[a-z0-9-]+$").unwrap();
+        assert_eq!(
+            normalize_topic(" my-topic ", Some(&slug)).unwrap(),
+            "my-topic"
+        );
+        assert_eq!(
+            normalize_topic("My Topic!", Some(&slug))
+                .unwrap_err()
+                .message,
+            "Topic does not match topic_name_pattern"
+        );
+        assert!(normalize_topic("My Topic!", None).is_ok());
+        // 结构检查先于 pattern
+        assert_eq!(
+            normalize_topic("a//b", Some(&slug)).unwrap_err().message,
+            "Topic is invalid"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use axum::extract::{FromRef, FromRequestParts, Query};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::models::AppState;
+
+#[derive(Clone, Copy)]
+pub struct PageConfig {
+    pub default_size: usize,
+    pub max_size: usize,
+}
+
+// handler 只需要分页配置时可以直接 State<PageConfig>
+impl FromRef<Arc<AppState>> for PageConfig {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        state.page_config
+    }
+}
+
+/// `?limit=&offset=`, with the limit defaulted and clamped by config.
+pub struct Pagination {
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl Pagination {
+    pub fn apply<T>(&self, items: impl IntoIterator<Item = T>) -> Vec<T> {
+        items
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit)
+            .collect()
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Pagination
+where
+    PageConfig: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = PageConfig::from_ref(state);
+        let invalid = || {
+            AppError::from((
+                StatusCode::BAD_REQUEST,
+                "invalid_pagination",
+                "Invalid pagination",
+            ))
+        };
+        let Query(query) = Query::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| invalid())?;
+        let parse = |key: &str, default: usize| {
+            query
+                .get(key)
+                .map_or(Ok(default), |s| s.parse::<usize>())
+                .map_err(|_| invalid())
+        };
+
+        Ok(Self {
+            limit: parse("limit", config.default_size)?.min(config.max_size),
+            offset: parse("offset", 0)?,
+        })
+    }
+}
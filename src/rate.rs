@@ -0,0 +1,209 @@
+use axum::extract::{ConnectInfo, FromRef, FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::error::AppError;
+use crate::models::{AppState, UserStatus, now};
+use crate::token::OptionalToken;
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+// 令牌桶, 容量为每分钟请求数; per_minute = 0 表示不限
+pub struct RateLimiter<K> {
+    per_minute: u32,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, key: K) -> Result<(), AppError> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = self.per_minute as f64;
+        let rate = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > 10_000 {
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.last).as_secs_f64() * rate < capacity
+            });
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            last: now,
+        });
+        bucket.tokens =
+            (bucket.tokens + now.duration_since(bucket.last).as_secs_f64() * rate).min(capacity);
+        bucket.last = now;
+
+        match bucket.tokens >= 1.0 {
+            true => {
+                bucket.tokens -= 1.0;
+                Ok(())
+            }
+            false => Err(AppError::RateLimited {
+                retry_after: ((1.0 - bucket.tokens) / rate).ceil() as u64,
+            }),
+        }
+    }
+
+    /// Current bucket state without consuming, or `None` when unlimited.
+    pub fn peek(&self, key: &K) -> Option<RateState> {
+        if self.per_minute == 0 {
+            return None;
+        }
+        let capacity = self.per_minute as f64;
+        let rate = capacity / 60.0;
+        let tokens: f64 = self.buckets.lock().unwrap().get(key).map_or(capacity, |b| {
+            (b.tokens + b.last.elapsed().as_secs_f64() * rate).min(capacity)
+        });
+        Some(RateState {
+            limit: self.per_minute,
+            remaining: tokens.floor() as u32,
+            reset: ((capacity - tokens) / rate).ceil() as u64,
+        })
+    }
+}
+
+pub struct RateState {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset: u64, // 桶回满所需秒数
+}
+
+/// Adds `X-RateLimit-*` headers for the caller's bucket to every response.
+pub async fn rate_headers_middleware(
+    State(state): State<Arc<AppState>>,
+    ip: ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let uid: Option<u64> = match request
+        .extensions()
+        .get::<Arc<tokio::sync::Mutex<OptionalToken>>>()
+    {
+        Some(token) => token.lock().await.get_sub(),
+        None => None,
+    };
+    let mut response = next.run(request).await;
+
+    let rate_state: Option<RateState> = match uid {
+        Some(uid) => state.user_limiter.peek(&uid),
+        None => state.ip_limiter.peek(&ip.0),
+    };
+    if let Some(rate_state) = rate_state {
+        let headers = response.headers_mut();
+        headers.insert("X-RateLimit-Limit", HeaderValue::from(rate_state.limit));
+        headers.insert(
+            "X-RateLimit-Remaining",
+            HeaderValue::from(rate_state.remaining),
+        );
+        headers.insert("X-RateLimit-Reset", HeaderValue::from(rate_state.reset));
+    }
+    response
+}
+
+// 写接口限流: 登录用户按 uid, 匿名按 IP, 管理员不限;
+// 注册不满 new_account_grace_secs 的账号还要过更严的 young_limiter
+fn check_rate(state: &AppState, uid: Option<u64>, ip: IpAddr) -> Result<(), AppError> {
+    let Some(uid) = uid else {
+        return state.ip_limiter.check(ip);
+    };
+    let (is_admin, created_at): (bool, i64) = state.transaction(|helper| {
+        Ok(helper
+            .get::<_, UserStatus>(&uid)?
+            .map_or((false, 0), |s| (s.is_admin(), s.data().created_at)))
+    })?;
+    if is_admin {
+        return Ok(());
+    }
+    if now() - created_at < state.new_account_grace_secs {
+        state.young_limiter.check(uid)?;
+    }
+    state.user_limiter.check(uid)
+}
+
+/// Spends one unit of the caller's write budget on every non-GET request,
+/// before the handler runs. GitHub's webhook and `/logout` are exempt.
+pub async fn rate_limit_middleware(
+    State(state): State<Arc<AppState>>,
+    ip: ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_read: bool = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if is_read || matches!(request.uri().path(), "/oauth/github/webhook" | "/logout") {
+        return next.run(request).await;
+    }
+    let uid: Option<u64> = match request
+        .extensions()
+        .get::<Arc<tokio::sync::Mutex<OptionalToken>>>()
+    {
+        Some(token) => token.lock().await.get_sub(),
+        None => None,
+    };
+    match check_rate(&state, uid, ip.0) {
+        Ok(()) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Client address. `X-Forwarded-For` is honoured only when the peer is one of
+/// `trusted_proxies`; the client is then the last hop that is not a trusted proxy.
+pub struct ClientIp(pub IpAddr);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp
+where
+    Arc<AppState>: FromRef<S>,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = Arc::<AppState>::from_ref(state);
+        let Some(peer) = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+        else {
+            return Ok(Self(IpAddr::from([0, 0, 0, 0])));
+        };
+        if !state.trusted_proxies.contains(&peer) {
+            return Ok(Self(peer));
+        }
+        // 从右往左跳过受信代理; 解析不了的项之前的内容都不可信
+        let hops: Vec<&str> = parts
+            .headers
+            .get_all("X-Forwarded-For")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect();
+        let client: Option<IpAddr> = hops
+            .iter()
+            .rev()
+            .map_while(|hop| hop.trim().parse::<IpAddr>().ok())
+            .find(|ip| !state.trusted_proxies.contains(ip));
+        Ok(Self(client.unwrap_or(peer)))
+    }
+}
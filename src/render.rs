@@ -0,0 +1,62 @@
+use ammonia::Builder;
+use once_cell::sync::Lazy;
+use pulldown_cmark::{Event, Parser, html};
+use std::collections::HashSet;
+
+// pulldown-cmark 负责解析, ammonia 再按白名单清洗一遍.
+// 输入里的 HTML 在解析时就转成文本, 和之前的行为一致; 链接只允许 http(s).
+
+static SANITIZER: Lazy<Builder<'static>> = Lazy::new(|| {
+    let mut builder = Builder::empty();
+    builder
+        .tags(HashSet::from([
+            "p",
+            "br",
+            "strong",
+            "em",
+            "code",
+            "pre",
+            "a",
+            "ul",
+            "ol",
+            "li",
+            "blockquote",
+        ]))
+        .add_tag_attributes("a", ["href"])
+        .url_schemes(HashSet::from(["http", "https"]))
+        .link_rel(Some("nofollow noopener"));
+    builder
+});
+
+/// Renders CommonMark to sanitized HTML; single newlines become `<br>`.
+pub fn render_markdown(markdown: &str) -> String {
+    let events = Parser::new(markdown).map(|event| match event {
+        Event::SoftBreak => Event::HardBreak,
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        event => event,
+    });
+    let mut out = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut out, events);
+    SANITIZER.clean(&out).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_and_sanitizes() {
+        assert_eq!(
+            render_markdown("**bold** *em* `a*b*`"),
+            "<p><strong>bold</strong> <em>em</em> <code>a*b*</code></p>\n"
+        );
+        let html = render_markdown("[a](http://x/*y*) and [b](javascript:alert(1))");
+        assert!(html.contains(r#"<a href="http://x/*y*" rel="nofollow noopener">a</a>"#));
+        assert!(!html.contains("<em>") && !html.contains("javascript:"));
+
+        let html = render_markdown("<script>alert(1)</script>\n\n<img src=x onerror=alert(1)>");
+        assert!(!html.contains("<script") && !html.contains("<img"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert_eq!(render_markdown("a\nb"), "<p>a<br>\nb</p>\n");
+    }
+}
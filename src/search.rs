@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::error::AppError;
+use crate::models::{DbHelper, DbType, Topic};
+
+// 倒排索引: !fts<word> -> topic names
+#[derive(Serialize, Deserialize, Default)]
+pub struct Posting(pub HashSet<String>);
+
+impl DbType for Posting {
+    fn prefix() -> &'static str {
+        "!fts"
+    }
+
+    fn name() -> &'static str {
+        "Index"
+    }
+}
+
+// 描述去重索引: !deschash<hash> -> 描述相同的 topic names
+#[derive(Serialize, Deserialize, Default)]
+pub struct DescGroup(pub HashSet<String>);
+
+impl DbType for DescGroup {
+    fn prefix() -> &'static str {
+        "!deschash"
+    }
+
+    fn name() -> &'static str {
+        "Description hash"
+    }
+}
+
+/// Hash of the description with case and whitespace normalized; `None` if empty.
+pub fn description_hash(description: &str) -> Option<String> {
+    use sha2::{Digest, Sha256};
+    let normalized: String = description
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    if normalized.is_empty() {
+        return None;
+    }
+    let digest = Sha256::digest(normalized.as_bytes());
+    Some(digest[..8].iter().map(|b| format!("{b:02x}")).collect())
+}
+
+pub fn tokenize(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 2)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Updates the full-text and duplicate-description indexes for an edit.
+pub fn reindex(
+    helper: &DbHelper<'_>,
+    topic: &str,
+    old_text: &str,
+    new_text: &str,
+) -> Result<(), AppError> {
+    let (old, new) = (tokenize(old_text), tokenize(new_text));
+
+    for word in old.difference(&new) {
+        let mut posting: Posting = helper.get(word)?.unwrap_or_default();
+        posting.0.remove(topic);
+        match posting.0.is_empty() {
+            true => helper.remove::<_, Posting>(word)?,
+            false => helper.insert(word, &posting)?,
+        }
+    }
+    for word in new.difference(&old) {
+        let mut posting: Posting = helper.get(word)?.unwrap_or_default();
+        posting.0.insert(topic.to_string());
+        helper.insert(word, &posting)?;
+    }
+    rehash(helper, topic, old_text, new_text)
+}
+
+fn rehash(helper: &DbHelper<'_>, topic: &str, old: &str, new: &str) -> Result<(), AppError> {
+    let (old, new) = (description_hash(old), description_hash(new));
+    if old == new {
+        return Ok(());
+    }
+    if let Some(hash) = old {
+        let mut group: DescGroup = helper.get(&hash)?.unwrap_or_default();
+        group.0.remove(topic);
+        match group.0.is_empty() {
+            true => helper.remove::<_, DescGroup>(&hash)?,
+            false => helper.insert(&hash, &group)?,
+        }
+    }
+    if let Some(hash) = new {
+        let mut group: DescGroup = helper.get(&hash)?.unwrap_or_default();
+        group.0.insert(topic.to_string());
+        helper.insert(&hash, &group)?;
+    }
+    Ok(())
+}
+
+pub fn search(helper: &DbHelper<'_>, query: &str) -> Result<Vec<String>, AppError> {
+    let mut result: Option<HashSet<String>> = None;
+    for word in tokenize(query) {
+        let posting: Posting = helper.get(&word)?.unwrap_or_default();
+        result = Some(match result {
+            Some(acc) => acc.intersection(&posting.0).cloned().collect(),
+            None => posting.0,
+        });
+        if result.as_ref().is_some_and(HashSet::is_empty) {
+            break;
+        }
+    }
+
+    let mut topics: Vec<String> = result.unwrap_or_default().into_iter().collect();
+    topics.sort();
+    Ok(topics)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sort {
+    Relevance, // 查询词在描述中出现的次数
+    Recent,    // updated_at
+    Popular,   // tag 票数总和
+}
+
+impl std::str::FromStr for Sort {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relevance" => Ok(Self::Relevance),
+            "recent" => Ok(Self::Recent),
+            "popular" => Ok(Self::Popular),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Like `search`, ordered by `sort` descending; ties keep name order.
+pub fn search_sorted(
+    helper: &DbHelper<'_>,
+    query: &str,
+    sort: Sort,
+) -> Result<Vec<String>, AppError> {
+    let words: HashSet<String> = tokenize(query);
+    let mut scored: Vec<(i64, String)> = Vec::new();
+    for name in search(helper, query)? {
+        let Some(topic) = helper.get::<_, Topic>(&name)?.filter(|t| !t.is_private()) else {
+            continue;
+        };
+        let score: i64 = match sort {
+            Sort::Relevance => topic
+                .description
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| words.contains(&word.to_lowercase()))
+                .count() as i64,
+            Sort::Recent => topic.updated_at,
+            Sort::Popular => topic.tags.values().map(|&c| c as i64).sum(),
+        };
+        scored.push((score, name));
+    }
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    Ok(scored.into_iter().map(|(_, name)| name).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::with_transaction;
+
+    #[test]
+    fn search_intersects_postings() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        with_transaction(&db, |helper| {
+            reindex(&helper, "rust", "", "A fast systems language")?;
+            reindex(&helper, "go", "", "A simple systems-level language")?;
+            reindex(&helper, "cat", "", "A small furry animal")
+        })
+        .unwrap();
+
+        let search = |q: &str| with_transaction(&db, |helper| search(&helper, q)).unwrap();
+        assert_eq!(search("SYSTEMS"), ["go", "rust"]);
+        assert_eq!(search("fast systems"), ["rust"]);
+        assert!(search("fast animal").is_empty());
+        assert!(search("a").is_empty()); // 短于 2 个字符的词不建索引
+
+        // 编辑后旧词的倒排项被移除
+        with_transaction(&db, |helper| {
+            reindex(
+                &helper,
+                "rust",
+                "A fast systems language",
+                "A fast language",
+            )
+        })
+        .unwrap();
+        assert_eq!(search("systems"), ["go"]);
+    }
+}
@@ -1,15 +1,127 @@
 use axum::body::Body;
-use axum::extract::Request;
+use axum::extract::{FromRequestParts, Request, State};
 use axum::http::header::{self, HeaderValue};
+use axum::http::request::Parts;
 use axum::http::{HeaderMap, StatusCode};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, Mac};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use sha2::Sha256;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
 use tokio::sync::Mutex;
+use tracing::info;
 
-static TOKEN_SECRET_KEY: Lazy<Arc<[u8; 32]>> = Lazy::new(|| Arc::new(rand::random()));
+use crate::error::AppError;
+use crate::models::{AppState, DbType, Revoked, get_one, scan_all};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Lifetime of a login token; refreshing moves `iat` but never `exp`.
+pub const TOKEN_TTL_SECS: i64 = 2_000_000;
+
+static TOKEN_SECRET_KEY: Lazy<RwLock<[u8; 32]>> = Lazy::new(|| RwLock::new(rand::random()));
+
+fn secret_key() -> [u8; 32] {
+    *TOKEN_SECRET_KEY.read().unwrap()
+}
+
+// 配置了 token_secret 时不允许在线轮换, 否则重启后又变回配置里的值
+static KEY_FROM_CONFIG: AtomicBool = AtomicBool::new(false);
+
+/// Uses a fixed signing key from config (64 hex chars or base64 of 32 bytes).
+pub fn set_secret_key(encoded: &str) -> Result<(), &'static str> {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+    let encoded = encoded.trim();
+    let bytes: Vec<u8> = match encoded.len() == 64 && encoded.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        true => (0..64)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&encoded[i..i + 2], 16).unwrap_or_default())
+            .collect(),
+        false => STANDARD
+            .decode(encoded)
+            .map_err(|_| "token_secret is not hex or base64")?,
+    };
+    let key: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| "token_secret must be 32 bytes")?;
+    *TOKEN_SECRET_KEY.write().unwrap() = key;
+    KEY_FROM_CONFIG.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn is_key_from_config() -> bool {
+    KEY_FROM_CONFIG.load(Ordering::Relaxed)
+}
+
+/// Loads the signing key from `path`, creating it if it does not exist yet.
+pub fn load_secret_key(path: impl AsRef<Path>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let path = path.as_ref();
+    if !path.exists() {
+        return rotate_secret_key(path);
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    let key: [u8; 32] = std::fs::read(path)?
+        .try_into()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "bad key length"))?;
+    *TOKEN_SECRET_KEY.write().unwrap() = key;
+    Ok(())
+}
+
+/// Generates and persists a new signing key, invalidating every issued token.
+pub fn rotate_secret_key(path: impl AsRef<Path>) -> std::io::Result<()> {
+    let key: [u8; 32] = rand::random();
+    write_key_file(path.as_ref(), &key)?;
+    *TOKEN_SECRET_KEY.write().unwrap() = key;
+    info!("token signing key rotated ({})", path.as_ref().display());
+    Ok(())
+}
+
+// 只有属主可读写; 已存在的文件 (如旧版本以 0644 创建的) 也收紧权限
+fn write_key_file(path: &Path, key: &[u8; 32]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(key)
+}
+
+/// Authorization scheme, shared by every encode and decode path.
+pub const SCHEME: &str = "Bearer ";
+
+/// Cookie carrying the token when `use_cookie_auth` is on.
+pub const COOKIE_NAME: &str = "tagme_token";
+
+// cookie 的 Path 跟随 base_path
+fn cookie_path(state: &AppState) -> &str {
+    match state.base_path.is_empty() {
+        true => "/",
+        false => &state.base_path,
+    }
+}
+
+/// `Set-Cookie` value that makes the browser drop the token cookie.
+pub fn clear_cookie(state: &AppState) -> HeaderValue {
+    HeaderValue::try_from(format!(
+        "{COOKIE_NAME}=; Path={}; Max-Age=0; HttpOnly; Secure; SameSite=Lax",
+        cookie_path(state)
+    ))
+    .unwrap_or(HeaderValue::from_static(""))
+}
+
+/// Format version written as the first byte of every encoded token.
+/// Bump it whenever the fields of `Token` change, and keep decoding the old ones.
+pub const TOKEN_VERSION: u8 = 1;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Token {
@@ -22,20 +134,21 @@ pub struct Token {
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match String::try_from(self) {
-            Ok(s) => write!(f, "Bearer {s}"),
-            Err(_) => write!(f, "Bearer [invalid]"),
+            Ok(s) => write!(f, "{SCHEME}{s}"),
+            Err(_) => write!(f, "{SCHEME}[invalid]"),
         }
     }
 }
 
+// 接受带或不带 scheme 的形式
 impl TryFrom<&str> for Token {
     type Error = ();
     fn try_from(s: &str) -> Result<Self, Self::Error> {
         use base64::{Engine as _, engine::general_purpose::STANDARD};
-        STANDARD
-            .decode(s)
-            .map_err(|_| ())
-            .and_then(|bytes| rmp_serde::from_slice(&bytes).map_err(|_| ()))
+        let bytes: Vec<u8> = STANDARD
+            .decode(s.strip_prefix(SCHEME).unwrap_or(s))
+            .map_err(|_| ())?;
+        Self::decode_versioned(&bytes)
     }
 }
 
@@ -44,9 +157,8 @@ impl TryFrom<&HeaderMap> for Token {
     fn try_from(headers: &HeaderMap) -> Result<Self, Self::Error> {
         headers
             .get(header::AUTHORIZATION)
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| s.strip_prefix("Bearer "))
-            .map_or(Err(()), |s| Self::try_from(s))
+            .and_then(Self::from_header_value)
+            .ok_or(())
     }
 }
 
@@ -54,17 +166,16 @@ impl TryFrom<&Token> for String {
     type Error = ();
     fn try_from(token: &Token) -> Result<Self, Self::Error> {
         use base64::{Engine as _, engine::general_purpose::STANDARD};
-        rmp_serde::to_vec(token)
-            .map(|bytes| STANDARD.encode(bytes))
-            .map_err(|_| ())
+        let mut bytes: Vec<u8> = vec![TOKEN_VERSION];
+        rmp_serde::encode::write(&mut bytes, token).map_err(|_| ())?;
+        Ok(STANDARD.encode(bytes))
     }
 }
 
 impl TryFrom<&Token> for HeaderValue {
     type Error = ();
     fn try_from(token: &Token) -> Result<Self, Self::Error> {
-        String::try_from(token)
-            .and_then(|s| HeaderValue::try_from(&format!("Bearer {s}")).map_err(|_| ()))
+        token.to_header_value().ok_or(())
     }
 }
 
@@ -78,21 +189,86 @@ impl IntoResponse for Token {
 }
 
 impl Token {
+    // v0 没有版本字节, 直接以 rmp 的 fixarray 头 (0x90..=0x9f) 开头, 与版本号不冲突
+    fn decode_versioned(bytes: &[u8]) -> Result<Self, ()> {
+        match bytes.split_first().ok_or(())? {
+            (0x90..=0x9f, _) => rmp_serde::from_slice(bytes).map_err(|_| ()),
+            (1, rest) => rmp_serde::from_slice(rest).map_err(|_| ()),
+            _ => Err(()),
+        }
+    }
+
+    /// Parses an `Authorization` value; the scheme is required here.
+    pub fn from_header_value(value: &HeaderValue) -> Option<Self> {
+        value
+            .to_str()
+            .ok()
+            .and_then(|s| s.strip_prefix(SCHEME))
+            .and_then(|s| Self::try_from(s).ok())
+    }
+
+    pub fn from_cookie(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().strip_prefix(COOKIE_NAME)?.strip_prefix('='))
+            .find_map(|value| Self::try_from(value).ok())
+    }
+
+    /// `Set-Cookie` value for this token, expiring together with it.
+    pub fn to_cookie(&self, state: &AppState) -> Option<HeaderValue> {
+        let encoded = String::try_from(self).ok()?;
+        let max_age = self.exp - time::UtcDateTime::now().unix_timestamp();
+        HeaderValue::try_from(format!(
+            "{COOKIE_NAME}={encoded}; Path={}; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax",
+            cookie_path(state)
+        ))
+        .ok()
+    }
+
+    pub fn to_header_value(&self) -> Option<HeaderValue> {
+        let encoded = String::try_from(self).ok()?;
+        HeaderValue::try_from(format!("{SCHEME}{encoded}")).ok()
+    }
+
+    // sub || iat || exp, 均为小端
+    fn mac(secret_key: &[u8; 32], sub: u64, iat: i64, exp: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(secret_key).expect("HMAC takes any key length");
+        mac.update(&sub.to_le_bytes());
+        mac.update(&iat.to_le_bytes());
+        mac.update(&exp.to_le_bytes());
+        mac
+    }
+
+    /// HMAC-SHA256 over `sub || iat || exp`, each little-endian.
     pub fn signature(secret_key: &[u8; 32], sub: u64, iat: i64, exp: i64) -> [u8; 32] {
-        use sha2::{Digest, Sha256};
-        let mut hasher = Sha256::new();
-        hasher.update(secret_key);
-        hasher.update(sub.to_ne_bytes());
-        hasher.update(iat.to_ne_bytes());
-        hasher.update(exp.to_ne_bytes());
-        hasher.finalize().into()
+        Self::mac(secret_key, sub, iat, exp)
+            .finalize()
+            .into_bytes()
+            .into()
     }
 
     pub fn new(sub: u64) -> Self {
-        let iat = time::UtcDateTime::now().unix_timestamp();
-        let exp = iat + 2_000_000;
+        Self::issued_at(sub, time::UtcDateTime::now().unix_timestamp())
+    }
+
+    /// Mints a token for `sub` that postdates its latest revocation, so
+    /// logging in again right after `/logout` is not caught by it.
+    pub fn issue(state: &AppState, sub: u64) -> Result<Self, AppError> {
+        let now = time::UtcDateTime::now().unix_timestamp();
+        let revoked_at: Option<i64> = get_one::<_, Revoked>(state.db(), &sub, false)?.map(|r| r.0);
+        Ok(Self::issued_at(
+            sub,
+            revoked_at.map_or(now, |r| now.max(r + 1)),
+        ))
+    }
+
+    fn issued_at(sub: u64, iat: i64) -> Self {
+        let exp = iat + TOKEN_TTL_SECS;
         Self {
-            sign: Self::signature(&TOKEN_SECRET_KEY, sub, iat, exp),
+            sign: Self::signature(&secret_key(), sub, iat, exp),
             sub,
             iat,
             exp,
@@ -101,15 +277,19 @@ impl Token {
 
     pub fn is_valid(&self) -> bool {
         let now = time::UtcDateTime::now().unix_timestamp();
-        self.sign == Self::signature(&TOKEN_SECRET_KEY, self.sub, self.iat, self.exp)
+        // verify_slice 按常数时间比较
+        Self::mac(&secret_key(), self.sub, self.iat, self.exp)
+            .verify_slice(&self.sign)
+            .is_ok()
             && (self.iat - now) + (self.exp - now) > 0
     }
 
     pub fn update(self) -> Option<Self> {
         self.is_valid().then(|| {
-            let iat = time::UtcDateTime::now().unix_timestamp();
+            // iat 不回退, 否则 Token::issue 签发的 token 刷新后会落回吊销的那一秒
+            let iat = time::UtcDateTime::now().unix_timestamp().max(self.iat);
             Self {
-                sign: Self::signature(&TOKEN_SECRET_KEY, self.sub, iat, self.exp),
+                sign: Self::signature(&secret_key(), self.sub, iat, self.exp),
                 sub: self.sub,
                 iat,
                 exp: self.exp,
@@ -118,42 +298,470 @@ impl Token {
     }
 }
 
-pub struct OptionalToken(Option<Token>);
+/// Read-only grant for a single topic, for embedding widgets elsewhere.
+/// Signed with the same key as `Token` but never accepted as one.
+#[derive(Serialize, Deserialize)]
+pub struct EmbedToken {
+    pub topic: String,
+    exp: i64,
+    sign: [u8; 32],
+}
+
+impl EmbedToken {
+    // "embed" 前缀保证与 Token 的签名不会互相冒用
+    fn mac(secret_key: &[u8; 32], topic: &str, exp: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(secret_key).expect("HMAC takes any key length");
+        mac.update(b"embed");
+        mac.update(&(topic.len() as u64).to_le_bytes());
+        mac.update(topic.as_bytes());
+        mac.update(&exp.to_le_bytes());
+        mac
+    }
+
+    pub fn new(topic: String, ttl_secs: i64) -> Self {
+        let exp = time::UtcDateTime::now().unix_timestamp() + ttl_secs;
+        Self {
+            sign: Self::mac(&secret_key(), &topic, exp)
+                .finalize()
+                .into_bytes()
+                .into(),
+            topic,
+            exp,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        Self::mac(&secret_key(), &self.topic, self.exp)
+            .verify_slice(&self.sign)
+            .is_ok()
+            && self.exp > time::UtcDateTime::now().unix_timestamp()
+    }
+
+    pub fn encode(&self) -> String {
+        use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+        URL_SAFE_NO_PAD.encode(rmp_serde::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(s: &str) -> Option<Self> {
+        use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+        let bytes = URL_SAFE_NO_PAD.decode(s).ok()?;
+        rmp_serde::from_slice(&bytes).ok()
+    }
+}
+
+/// Cookie binding an OAuth `state` to the browser that started the login.
+pub const OAUTH_STATE_COOKIE: &str = "tagme_oauth_state";
+
+/// `state` for the GitHub login round trip. The nonce is also set as a
+/// short-lived cookie, so a callback started from another browser (login
+/// CSRF) is rejected; the signature stops anyone else from minting one.
+/// It also records the `redirect_uri` the authorize URL was built with.
+#[derive(Serialize, Deserialize)]
+pub struct OAuthState {
+    nonce: u64,
+    exp: i64,
+    pub redirect_uri: String, // 空表示使用 GitHub 应用里登记的地址
+    sign: [u8; 32],
+}
+
+impl OAuthState {
+    pub const TTL_SECS: i64 = 600;
+
+    // "oauth" 前缀保证与其他签名不会互相冒用
+    fn mac(secret_key: &[u8; 32], nonce: u64, exp: i64, redirect_uri: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(secret_key).expect("HMAC takes any key length");
+        mac.update(b"oauth");
+        mac.update(&nonce.to_le_bytes());
+        mac.update(&exp.to_le_bytes());
+        mac.update(redirect_uri.as_bytes());
+        mac
+    }
+
+    pub fn issue(redirect_uri: &str) -> Self {
+        let nonce: u64 = rand::random();
+        let exp = time::UtcDateTime::now().unix_timestamp() + Self::TTL_SECS;
+        Self {
+            sign: Self::mac(&secret_key(), nonce, exp, redirect_uri)
+                .finalize()
+                .into_bytes()
+                .into(),
+            nonce,
+            exp,
+            redirect_uri: redirect_uri.to_string(),
+        }
+    }
+
+    /// Signature and expiry hold, and the request carries the matching cookie.
+    pub fn is_valid_for(&self, headers: &HeaderMap) -> bool {
+        let nonce = format!("{:016x}", self.nonce);
+        let has_cookie = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| {
+                pair.trim()
+                    .strip_prefix(OAUTH_STATE_COOKIE)?
+                    .strip_prefix('=')
+            })
+            .any(|value| value == nonce);
+        Self::mac(&secret_key(), self.nonce, self.exp, &self.redirect_uri)
+            .verify_slice(&self.sign)
+            .is_ok()
+            && self.exp > time::UtcDateTime::now().unix_timestamp()
+            && has_cookie
+    }
+
+    /// `Set-Cookie` value carrying the nonce until the callback.
+    pub fn to_cookie(&self, state: &AppState) -> Option<HeaderValue> {
+        HeaderValue::try_from(format!(
+            "{OAUTH_STATE_COOKIE}={:016x}; Path={}; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+            self.nonce,
+            cookie_path(state),
+            Self::TTL_SECS
+        ))
+        .ok()
+    }
+
+    /// `Set-Cookie` value that drops the nonce once the callback has used it.
+    pub fn clear_cookie(state: &AppState) -> HeaderValue {
+        HeaderValue::try_from(format!(
+            "{OAUTH_STATE_COOKIE}=; Path={}; Max-Age=0; HttpOnly; Secure; SameSite=Lax",
+            cookie_path(state)
+        ))
+        .unwrap_or(HeaderValue::from_static(""))
+    }
+
+    pub fn encode(&self) -> String {
+        use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+        URL_SAFE_NO_PAD.encode(rmp_serde::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(s: &str) -> Option<Self> {
+        use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+        let bytes = URL_SAFE_NO_PAD.decode(s).ok()?;
+        rmp_serde::from_slice(&bytes).ok()
+    }
+}
+
+/// `X-Embed-Token` header or `?embed=`; present but invalid is a `401`.
+pub struct EmbedGrant(pub Option<EmbedToken>);
+
+impl<S: Send + Sync> FromRequestParts<S> for EmbedGrant {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let from_query: Option<&str> = parts
+            .uri
+            .query()
+            .and_then(|q| q.split('&').find_map(|pair| pair.strip_prefix("embed=")));
+        let raw: Option<&str> = parts
+            .headers
+            .get("X-Embed-Token")
+            .and_then(|v| v.to_str().ok())
+            .or(from_query);
+        match raw {
+            None => Ok(Self(None)),
+            Some(raw) => match EmbedToken::decode(raw).filter(EmbedToken::is_valid) {
+                Some(token) => Ok(Self(Some(token))),
+                None => Err((StatusCode::UNAUTHORIZED, "Invalid embed token")),
+            },
+        }
+    }
+}
+
+pub enum ApiScope {
+    Read,
+    Write(u64), // 固定的服务 uid
+}
+
+#[derive(Default)]
+pub struct OptionalToken {
+    token: Option<Token>,
+    service: Option<u64>, // 写 API key 对应的服务身份
+    from_cookie: bool,    // 刷新后的 token 按原来的渠道写回
+}
 
 impl OptionalToken {
-    pub fn auth(&self) -> Result<u64, (StatusCode, &'static str)> {
+    pub fn auth(&self) -> Result<u64, AppError> {
         self.get_sub()
-            .ok_or((StatusCode::UNAUTHORIZED, "Login required"))
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "login_required", "Login required").into())
+    }
+
+    /// Like `auth`, but refuses API-key service identities outright.
+    /// Callers still have to check `UserStatus::as_admin`.
+    pub fn auth_admin(&self) -> Result<u64, AppError> {
+        match self.is_service() {
+            true => Err((StatusCode::FORBIDDEN, "admin_required", "Admin required").into()),
+            false => self.auth(),
+        }
+    }
+
+    pub fn is_service(&self) -> bool {
+        self.service.is_some()
     }
 
     pub fn get_sub(&self) -> Option<u64> {
-        self.as_ref().map(|t| t.sub)
+        self.as_ref().map(|t| t.sub).or(self.service)
     }
 
     pub fn as_ref(&self) -> Option<&Token> {
-        self.0.as_ref()
+        self.token.as_ref()
+    }
+
+    pub fn is_from_cookie(&self) -> bool {
+        self.from_cookie
+    }
+
+    /// Swaps in a newly issued token; the middleware writes it back on the same channel.
+    pub fn replace(&mut self, token: Token) {
+        self.token = Some(token);
     }
 }
 
-pub async fn token_middleware(mut request: Request, next: Next) -> Response {
-    let token = Arc::new(Mutex::new(OptionalToken(
-        Token::try_from(request.headers())
-            .ok()
+// 必须在 update 之前检查, 否则刷新后的 iat 会绕过吊销; 查询失败时按已吊销处理.
+// 与吊销同一秒签发的也算, 吊销时已存在的 token 都不能留下.
+// 吊销记录总是严格解码, 不受 lenient_decode 影响: 读不出来就按已吊销处理
+// 每个请求都会走到这里, 只读一条记录, 不开事务
+fn is_revoked(state: &AppState, token: &Token) -> bool {
+    let Some(db) = state.db.get() else {
+        return false; // 启动中, 只有 /health 能走到这里
+    };
+    get_one::<_, Revoked>(db, &token.sub, false)
+        .map_or(true, |revoked| revoked.is_some_and(|r| token.iat <= r.0))
+}
+
+/// Drops revocations that no unexpired token can predate; returns how many were removed.
+pub fn sweep_revocations(db: &sled::Db) -> Result<usize, AppError> {
+    // 被吊销的 token 签发于 revoked_at 之前, exp 不会超过 revoked_at + TTL
+    let cutoff = time::UtcDateTime::now().unix_timestamp() - TOKEN_TTL_SECS;
+    let mut removed = 0;
+    // 跳过无法解码的记录, 不让一条坏数据挡住整轮清理
+    for (key, Revoked(revoked_at)) in scan_all::<Revoked>(db, true)? {
+        if revoked_at >= cutoff {
+            continue;
+        }
+        // 期间又被吊销过则保留新记录
+        let key = [Revoked::prefix().as_bytes(), &key].concat();
+        let current = rmp_serde::to_vec(&Revoked(revoked_at)).unwrap_or_default();
+        if let Ok(Ok(())) = db.compare_and_swap(key, Some(current), None::<Vec<u8>>) {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Header that cookie-authenticated writes must carry. Cross-site forms
+/// cannot set it, and a cross-origin script needs a CORS preflight to.
+pub const CSRF_HEADER: &str = "x-requested-with";
+
+pub async fn token_middleware(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let service: Option<u64> = match request.headers().get("X-Api-Key") {
+        None => None,
+        Some(key) => match key.to_str().ok().and_then(|k| state.api_keys.get(k)) {
+            Some(ApiScope::Read) => None,
+            Some(ApiScope::Write(uid)) => Some(*uid),
+            None => return (StatusCode::UNAUTHORIZED, "Invalid API key").into_response(),
+        },
+    };
+    // 请求头优先; 只有开启 use_cookie_auth 时才读 cookie
+    let (raw, from_cookie): (Option<Token>, bool) = match Token::try_from(request.headers()) {
+        Ok(token) => (Some(token), false),
+        Err(_) if state.cookie_auth => (Token::from_cookie(request.headers()), true),
+        Err(_) => (None, false),
+    };
+    // cookie 会随跨站请求带上; 写操作要求一个跨站表单发不出的头
+    let safe = request.method().is_safe();
+    if from_cookie && !safe && !request.headers().contains_key(CSRF_HEADER) {
+        return AppError::from((
+            StatusCode::FORBIDDEN,
+            "csrf_header_missing",
+            "Missing X-Requested-With header",
+        ))
+        .into_response();
+    }
+    let token = Arc::new(Mutex::new(OptionalToken {
+        token: raw
+            .filter(|t| !is_revoked(&state, t))
             .and_then(|t| t.update()),
-    )));
+        service,
+        from_cookie,
+    }));
     request.extensions_mut().insert(token.clone());
     let mut response = next.run(request).await;
 
-    if let Some(header) = token
-        .lock()
-        .await
-        .as_ref()
-        .and_then(|t| HeaderValue::try_from(t).ok())
-    {
-        response
-            .headers_mut()
-            .entry(header::AUTHORIZATION)
-            .or_insert(header);
+    let token = token.lock().await;
+    let Some(refreshed) = token.as_ref() else {
+        return response;
+    };
+    match token.from_cookie {
+        true => {
+            if let Some(cookie) = refreshed.to_cookie(&state) {
+                response.headers_mut().append(header::SET_COOKIE, cookie);
+            }
+        }
+        false => {
+            if let Some(header) = refreshed.to_header_value() {
+                response
+                    .headers_mut()
+                    .entry(header::AUTHORIZATION)
+                    .or_insert(header);
+            }
+        }
     }
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 签名密钥是进程级的, 会读写它的测试串行执行
+    static KEY_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn key_path(name: &str) -> std::path::PathBuf {
+        let nonce: u64 = rand::random();
+        std::env::temp_dir().join(format!("tagme-{name}-{nonce:x}.key"))
+    }
+
+    #[test]
+    fn rotation_invalidates_issued_tokens() {
+        use std::os::unix::fs::PermissionsExt;
+        let _lock = KEY_LOCK.lock().unwrap();
+        let path = key_path("rotate");
+        std::fs::write(&path, [0u8; 32]).unwrap(); // 默认 umask 下创建的旧文件
+        load_secret_key(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let token = Token::new(1);
+        assert!(token.is_valid());
+
+        rotate_secret_key(&path).unwrap();
+        assert!(!token.is_valid());
+        assert!(Token::new(1).is_valid());
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn flipped_bit_fails_verification() {
+        let _lock = KEY_LOCK.lock().unwrap();
+        let token = Token::new(42);
+        let mut forged = token.clone();
+        forged.sub ^= 1;
+        assert_ne!(
+            Token::signature(&secret_key(), forged.sub, forged.iat, forged.exp),
+            token.sign
+        );
+        assert!(token.is_valid());
+        assert!(!forged.is_valid());
+
+        let mut forged = token.clone();
+        forged.sign[31] ^= 0x80;
+        assert!(!forged.is_valid());
+    }
+
+    #[test]
+    fn header_round_trip_keeps_the_scheme() {
+        let _lock = KEY_LOCK.lock().unwrap();
+        let token = Token::new(7);
+        let same = |other: &Token| {
+            (other.sub, other.iat, other.exp, other.sign)
+                == (token.sub, token.iat, token.exp, token.sign)
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, token.to_header_value().unwrap());
+        assert!(
+            headers[header::AUTHORIZATION]
+                .as_bytes()
+                .starts_with(SCHEME.as_bytes())
+        );
+        assert!(same(&Token::try_from(&headers).unwrap()));
+        // Display 与 TryFrom<&str> 互为逆操作
+        assert_eq!(headers[header::AUTHORIZATION], token.to_string());
+        assert!(same(&Token::try_from(token.to_string().as_str()).unwrap()));
+
+        // 请求头里必须带 scheme
+        let bare = String::try_from(&token).unwrap();
+        headers.insert(header::AUTHORIZATION, HeaderValue::try_from(bare).unwrap());
+        assert!(Token::try_from(&headers).is_err());
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer ???"),
+        );
+        assert!(Token::try_from(&headers).is_err());
+    }
+
+    #[test]
+    fn decodes_v0_and_v1_but_not_unknown_versions() {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        let _lock = KEY_LOCK.lock().unwrap();
+        let token = Token::new(9);
+        let body: Vec<u8> = rmp_serde::to_vec(&token).unwrap();
+        let decode = |bytes: &[u8]| Token::try_from(STANDARD.encode(bytes).as_str());
+
+        // v0: 没有版本字节的 rmp 数组
+        let v0 = decode(&body).unwrap();
+        assert_eq!((v0.sub, v0.sign), (9, token.sign));
+        assert!(v0.is_valid());
+
+        let encoded = String::try_from(&token).unwrap();
+        assert_eq!(STANDARD.decode(&encoded).unwrap()[0], TOKEN_VERSION);
+        let v1 = Token::try_from(encoded.as_str()).unwrap();
+        assert_eq!((v1.sub, v1.sign), (9, token.sign));
+
+        assert!(decode(&[&[2u8][..], &body].concat()).is_err());
+        assert!(decode(&[]).is_err());
+    }
+
+    #[test]
+    fn sweep_keeps_revocations_that_can_still_match() {
+        use crate::models::with_transaction;
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let now = time::UtcDateTime::now().unix_timestamp();
+        with_transaction(&db, |helper| {
+            helper.insert(&1u64, &Revoked(now - TOKEN_TTL_SECS - 1))?;
+            helper.insert(&2u64, &Revoked(now - TOKEN_TTL_SECS + 60))?;
+            helper.insert(&3u64, &Revoked(now))
+        })
+        .unwrap();
+
+        assert_eq!(sweep_revocations(&db).unwrap(), 1);
+        assert!(get_one::<_, Revoked>(&db, &1u64, false).unwrap().is_none());
+        assert!(get_one::<_, Revoked>(&db, &2u64, false).unwrap().is_some());
+        assert!(get_one::<_, Revoked>(&db, &3u64, false).unwrap().is_some());
+        assert_eq!(sweep_revocations(&db).unwrap(), 0);
+    }
+
+    #[test]
+    fn persisted_key_survives_a_restart() {
+        let _lock = KEY_LOCK.lock().unwrap();
+        // 重启即进程级密钥被重新随机化, 再从配置或文件载入
+        let restart = || *TOKEN_SECRET_KEY.write().unwrap() = rand::random();
+
+        let path = key_path("restart");
+        load_secret_key(&path).unwrap();
+        let token = Token::new(1);
+        restart();
+        assert!(!token.is_valid());
+        load_secret_key(&path).unwrap();
+        assert!(token.is_valid() && Token::new(2).is_valid());
+        std::fs::remove_file(path).unwrap();
+
+        let secret = "ab".repeat(32);
+        set_secret_key(&secret).unwrap();
+        let token = Token::new(3);
+        restart();
+        set_secret_key(&secret).unwrap();
+        assert!(token.is_valid());
+        assert!(is_key_from_config());
+        KEY_FROM_CONFIG.store(false, Ordering::Relaxed);
+        assert!(set_secret_key("too short").is_err());
+    }
+}
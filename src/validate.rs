@@ -0,0 +1,51 @@
+use axum::Json;
+use axum::extract::{FromRef, FromRequest, Request};
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+
+use crate::error::{AppError, FieldError};
+use crate::models::AppState;
+
+/// Field rules for a request body, run by `ValidatedJson` before the handler.
+/// Limits that come from config are read from `state`.
+pub trait Validate {
+    /// Pushes every failure instead of stopping at the first.
+    fn validate(&self, state: &AppState, errors: &mut Vec<FieldError>);
+}
+
+/// `Json<T>` whose rejection (malformed body, missing field, wrong content
+/// type) is answered in the usual error envelope.
+pub struct AppJson<T>(pub T);
+
+impl<S: Send + Sync, T: DeserializeOwned> FromRequest<S> for AppJson<T> {
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(request, state).await?;
+        Ok(Self(value))
+    }
+}
+
+/// `Json<T>` that answers with all field errors when `T` fails validation
+/// (`400` for one, `422` for several).
+pub struct ValidatedJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ValidatedJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+    Arc<AppState>: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let app_state = Arc::<AppState>::from_ref(state);
+        let AppJson(value) = AppJson::<T>::from_request(request, state).await?;
+        let mut errors: Vec<FieldError> = Vec::new();
+        value.validate(&app_state, &mut errors);
+        match errors.is_empty() {
+            true => Ok(Self(value)),
+            false => Err(AppError::Validation(errors)),
+        }
+    }
+}